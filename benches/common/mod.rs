@@ -0,0 +1,192 @@
+// Shared by every bench: one adapter trait over all the locks we compare, and the workloads themselves, so each bench
+// file only decides what to measure and how to report it.
+
+use my_mutex_learning::{
+    Backoff, HybridMutex, McsMutex, PoisonSpinMutex, ReentrantSpinMutex, SpinMutex, SpinRwLock,
+    TicketMutex,
+};
+use std::cell::Cell;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub trait BenchLock: Send + Sync + 'static {
+    const NAME: &'static str;
+    fn new() -> Self;
+    /// Runs `f` on the counter with the lock held.
+    fn with(&self, f: impl FnOnce(&mut u64));
+}
+
+macro_rules! bench_lock {
+    ($($name:literal => $ty:ty = $new:expr, |$lock:ident, $f:ident| $with:expr;)*) => {
+        $(
+            impl BenchLock for $ty {
+                const NAME: &'static str = $name;
+                fn new() -> Self {
+                    $new
+                }
+                fn with(&self, $f: impl FnOnce(&mut u64)) {
+                    let $lock = self;
+                    $with
+                }
+            }
+        )*
+    };
+}
+
+bench_lock! {
+    "std::sync::Mutex" => std::sync::Mutex<u64> = std::sync::Mutex::new(0), |m, f| f(&mut m.lock().unwrap());
+    "SpinMutex" => SpinMutex<u64> = SpinMutex::new(0), |m, f| f(&mut m.lock());
+    "SpinMutex<Backoff>" => SpinMutex<u64, Backoff> = SpinMutex::with_relax(0), |m, f| f(&mut m.lock());
+    "TicketMutex" => TicketMutex<u64> = TicketMutex::new(0), |m, f| f(&mut m.lock());
+    "McsMutex" => McsMutex<u64> = McsMutex::new(0), |m, f| f(&mut m.lock());
+    "HybridMutex" => HybridMutex<u64> = HybridMutex::new(0), |m, f| f(&mut m.lock());
+    "SpinRwLock (write)" => SpinRwLock<u64> = SpinRwLock::new(0), |m, f| f(&mut m.write());
+    "PoisonSpinMutex" => PoisonSpinMutex<u64> = PoisonSpinMutex::new(0), |m, f| f(&mut m.lock().unwrap());
+    // Only hands out `&T`, so the counter sits in a `Cell`
+    "ReentrantSpinMutex" => ReentrantSpinMutex<Cell<u64>> = ReentrantSpinMutex::new(Cell::new(0)), |m, f| {
+        let guard = m.lock();
+        let mut value = guard.get();
+        f(&mut value);
+        guard.set(value);
+    };
+}
+
+/// Something to run once per lock type, since closures can't be generic.
+pub trait LockVisitor {
+    fn visit<L: BenchLock>(&mut self);
+}
+
+pub fn for_every_lock(v: &mut impl LockVisitor) {
+    v.visit::<std::sync::Mutex<u64>>();
+    v.visit::<SpinMutex<u64>>();
+    v.visit::<SpinMutex<u64, Backoff>>();
+    v.visit::<TicketMutex<u64>>();
+    v.visit::<McsMutex<u64>>();
+    v.visit::<HybridMutex<u64>>();
+    v.visit::<SpinRwLock<u64>>();
+    v.visit::<PoisonSpinMutex<u64>>();
+    v.visit::<ReentrantSpinMutex<Cell<u64>>>();
+}
+
+/// How much work happens while the lock is held.
+#[derive(Clone, Copy)]
+pub enum CriticalSection {
+    /// Just the increment, so the lock itself is all there is to measure.
+    Short,
+    /// Roughly a few hundred nanoseconds of busywork, about a small hash map update's worth.
+    Long,
+}
+
+impl CriticalSection {
+    pub const ALL: [Self; 2] = [Self::Short, Self::Long];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Short => "short",
+            Self::Long => "long",
+        }
+    }
+
+    pub fn run(self, counter: &mut u64) {
+        match self {
+            Self::Short => *counter += 1,
+            Self::Long => {
+                for i in 0..200 {
+                    *counter = black_box(counter.wrapping_add(i));
+                }
+            }
+        }
+    }
+}
+
+/// Every thread does `ops_per_thread` lock/work/unlock rounds on one shared lock. Returns the time the whole batch
+/// took, not counting spawning the threads.
+pub fn run_contended<L: BenchLock>(
+    threads: usize,
+    ops_per_thread: u64,
+    cs: CriticalSection,
+) -> Duration {
+    let lock = Arc::new(L::new());
+    // +1 so the clock only starts once every worker is spawned and waiting
+    let barrier = Arc::new(Barrier::new(threads + 1));
+
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let lock = lock.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                for _ in 0..ops_per_thread {
+                    lock.with(|counter| cs.run(counter));
+                }
+            })
+        })
+        .collect();
+
+    barrier.wait();
+    let start = Instant::now();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    start.elapsed()
+}
+
+/// Lets `threads` threads hammer one lock for `duration` and returns how many times each of them got it. A fair lock
+/// gives everyone about the same share.
+pub fn acquisitions_per_thread<L: BenchLock>(threads: usize, duration: Duration) -> Vec<u64> {
+    let lock = Arc::new(L::new());
+    let stop = Arc::new(AtomicBool::new(false));
+    let barrier = Arc::new(Barrier::new(threads + 1));
+
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let lock = lock.clone();
+            let stop = stop.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                let mut acquired = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    lock.with(|counter| CriticalSection::Short.run(counter));
+                    acquired += 1;
+                }
+                acquired
+            })
+        })
+        .collect();
+
+    barrier.wait();
+    thread::sleep(duration);
+    stop.store(true, Ordering::Relaxed);
+    handles.into_iter().map(|h| h.join().unwrap()).collect()
+}
+
+/// Coefficient of variation of the per-thread counts: 0 is perfectly fair, bigger means some threads starve.
+pub fn unfairness(counts: &[u64]) -> f64 {
+    let n = counts.len() as f64;
+    let mean = counts.iter().sum::<u64>() as f64 / n;
+    if mean == 0.0 {
+        return 0.0;
+    }
+    let variance = counts
+        .iter()
+        .map(|&c| (c as f64 - mean).powi(2))
+        .sum::<f64>()
+        / n;
+    variance.sqrt() / mean
+}
+
+/// Powers of two up to the number of cores, plus the core count itself. Spinning with more threads than cores mostly
+/// measures the scheduler, so we stop there.
+pub fn thread_counts() -> Vec<usize> {
+    let cores = thread::available_parallelism().map_or(4, |n| n.get());
+    let mut counts: Vec<_> = (0..)
+        .map(|shift| 1 << shift)
+        .take_while(|&n| n < cores)
+        .collect();
+    counts.push(cores);
+    counts
+}
@@ -0,0 +1,57 @@
+// Throughput of each lock as the number of threads fighting over it grows, with a short and a long critical section.
+// Every thread does `OPS_PER_THREAD` rounds on one shared counter, and we time the whole batch.
+//
+// `cargo bench --bench contention`
+
+#[allow(dead_code)] // each bench only uses part of it
+mod common;
+
+use common::{
+    for_every_lock, run_contended, thread_counts, BenchLock, CriticalSection, LockVisitor,
+};
+use criterion::measurement::WallTime;
+use criterion::{
+    criterion_group, criterion_main, BenchmarkGroup, BenchmarkId, Criterion, Throughput,
+};
+
+const OPS_PER_THREAD: u64 = 10_000;
+
+struct Contended<'a, 'c> {
+    group: &'a mut BenchmarkGroup<'c, WallTime>,
+    threads: usize,
+    cs: CriticalSection,
+}
+
+impl LockVisitor for Contended<'_, '_> {
+    fn visit<L: BenchLock>(&mut self) {
+        let (threads, cs) = (self.threads, self.cs);
+        self.group
+            .bench_with_input(BenchmarkId::new(L::NAME, threads), &threads, |b, &n| {
+                b.iter_custom(|iters| {
+                    (0..iters)
+                        .map(|_| run_contended::<L>(n, OPS_PER_THREAD, cs))
+                        .sum()
+                })
+            });
+    }
+}
+
+fn contention(c: &mut Criterion) {
+    for cs in CriticalSection::ALL {
+        let mut group = c.benchmark_group(format!("contention/{}", cs.name()));
+        // Every lock at every thread count adds up, fewer samples keeps a full run in minutes
+        group.sample_size(20);
+        for threads in thread_counts() {
+            group.throughput(Throughput::Elements(threads as u64 * OPS_PER_THREAD));
+            for_every_lock(&mut Contended {
+                group: &mut group,
+                threads,
+                cs,
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, contention);
+criterion_main!(benches);
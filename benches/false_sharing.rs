@@ -0,0 +1,88 @@
+// A striped counter: one lock per thread, and each thread only ever touches its own. Nothing is contended, so any
+// slowdown from packing the locks next to each other is pure false sharing. Compares a plain array of `SpinMutex`es
+// against `PaddedSpinMutex`es. Needs more than one core to show anything.
+//
+// `cargo bench --bench false_sharing`
+
+#[allow(dead_code)] // each bench only uses part of it
+mod common;
+
+use common::thread_counts;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use my_mutex_learning::{PaddedSpinMutex, SpinMutex};
+use std::ops::Deref;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const OPS_PER_THREAD: u64 = 100_000;
+
+fn run_striped<S>(stripes: Arc<Vec<S>>) -> Duration
+where
+    S: Deref<Target = SpinMutex<u64>> + Send + Sync + 'static,
+{
+    let threads = stripes.len();
+    let barrier = Arc::new(Barrier::new(threads + 1));
+    let handles: Vec<_> = (0..threads)
+        .map(|i| {
+            let stripes = stripes.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                for _ in 0..OPS_PER_THREAD {
+                    *stripes[i].lock() += 1;
+                }
+            })
+        })
+        .collect();
+
+    barrier.wait();
+    let start = Instant::now();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    start.elapsed()
+}
+
+// `&SpinMutex` through `Deref` like `PaddedSpinMutex` does, so both layouts run the exact same code
+struct Packed(SpinMutex<u64>);
+
+impl Deref for Packed {
+    type Target = SpinMutex<u64>;
+    fn deref(&self) -> &SpinMutex<u64> {
+        &self.0
+    }
+}
+
+fn false_sharing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("false_sharing");
+    for threads in thread_counts() {
+        group.throughput(Throughput::Elements(threads as u64 * OPS_PER_THREAD));
+        group.bench_with_input(BenchmarkId::new("packed", threads), &threads, |b, &n| {
+            b.iter_custom(|iters| {
+                (0..iters)
+                    .map(|_| {
+                        run_striped(Arc::new(
+                            (0..n).map(|_| Packed(SpinMutex::new(0))).collect(),
+                        ))
+                    })
+                    .sum()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("padded", threads), &threads, |b, &n| {
+            b.iter_custom(|iters| {
+                (0..iters)
+                    .map(|_| {
+                        let stripes: Vec<PaddedSpinMutex<u64>> =
+                            (0..n).map(|_| PaddedSpinMutex::new(0)).collect();
+                        run_striped(Arc::new(stripes))
+                    })
+                    .sum()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, false_sharing);
+criterion_main!(benches);
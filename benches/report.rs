@@ -0,0 +1,61 @@
+// Not a criterion bench: a quick pass over every lock that prints one markdown table, for when you want the overall
+// picture rather than criterion's careful per-benchmark statistics. Numbers are single runs, so expect some noise.
+//
+// `cargo bench --bench report`
+
+#[allow(dead_code)] // each bench only uses part of it
+mod common;
+
+use common::{
+    acquisitions_per_thread, for_every_lock, run_contended, thread_counts, unfairness, BenchLock,
+    CriticalSection, LockVisitor,
+};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const UNCONTENDED_OPS: u32 = 1_000_000;
+const CONTENDED_OPS_PER_THREAD: u64 = 20_000;
+const FAIRNESS_WINDOW: Duration = Duration::from_millis(200);
+
+struct Report {
+    threads: usize,
+}
+
+impl LockVisitor for Report {
+    fn visit<L: BenchLock>(&mut self) {
+        let lock = L::new();
+        let start = Instant::now();
+        for _ in 0..UNCONTENDED_OPS {
+            black_box(&lock).with(|counter| CriticalSection::Short.run(counter));
+        }
+        let uncontended = start.elapsed() / UNCONTENDED_OPS;
+
+        let throughput = |cs| {
+            let elapsed = run_contended::<L>(self.threads, CONTENDED_OPS_PER_THREAD, cs);
+            (self.threads as u64 * CONTENDED_OPS_PER_THREAD) as f64 / elapsed.as_secs_f64() / 1e6
+        };
+        let short = throughput(CriticalSection::Short);
+        let long = throughput(CriticalSection::Long);
+
+        let counts = acquisitions_per_thread::<L>(self.threads, FAIRNESS_WINDOW);
+        let unfair = unfairness(&counts);
+
+        println!(
+            "| {} | {:?} | {:.2} | {:.2} | {:.3} |",
+            L::NAME,
+            uncontended,
+            short,
+            long,
+            unfair
+        );
+    }
+}
+
+fn main() {
+    // `cargo bench` passes criterion-style flags to every bench, there's nothing to configure here
+    let threads = *thread_counts().last().unwrap();
+    println!("{threads} threads for the contended columns\n");
+    println!("| lock | uncontended lock+unlock | short CS (Mops/s) | long CS (Mops/s) | unfairness (CV) |");
+    println!("|---|---|---|---|---|");
+    for_every_lock(&mut Report { threads });
+}
@@ -0,0 +1,30 @@
+// What a lock costs when nobody else wants it: one lock/increment/unlock round on a single thread.
+//
+// `cargo bench --bench uncontended`
+
+#[allow(dead_code)] // each bench only uses part of it
+mod common;
+
+use common::{for_every_lock, BenchLock, CriticalSection, LockVisitor};
+use criterion::measurement::WallTime;
+use criterion::{criterion_group, criterion_main, BenchmarkGroup, Criterion};
+
+struct Uncontended<'a, 'c>(&'a mut BenchmarkGroup<'c, WallTime>);
+
+impl LockVisitor for Uncontended<'_, '_> {
+    fn visit<L: BenchLock>(&mut self) {
+        let lock = L::new();
+        self.0.bench_function(L::NAME, |b| {
+            b.iter(|| lock.with(|counter| CriticalSection::Short.run(counter)))
+        });
+    }
+}
+
+fn uncontended(c: &mut Criterion) {
+    let mut group = c.benchmark_group("uncontended");
+    for_every_lock(&mut Uncontended(&mut group));
+    group.finish();
+}
+
+criterion_group!(benches, uncontended);
+criterion_main!(benches);
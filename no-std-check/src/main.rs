@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use my_mutex_learning::{
+    Backoff, McsMutex, McsNode, RawSpinLock, SpinMutex, SpinRwLock, SpinRwLockUpgradeableGuard,
+    TicketMutex,
+};
+
+static GLOBAL: SpinMutex<i32> = SpinMutex::new(0);
+
+// Linked by the platform's C runtime, which is all we have without std
+#[no_mangle]
+extern "C" fn main(_argc: i32, _argv: *const *const u8) -> i32 {
+    let spin = SpinMutex::new(1);
+    *spin.lock() += 1;
+
+    *GLOBAL.lock() += 1;
+
+    let backoff = SpinMutex::<_, Backoff>::with_relax(1);
+    *backoff.try_lock().unwrap() += 1;
+
+    let ticket = TicketMutex::new(1);
+    *ticket.lock() += 1;
+
+    let mcs = McsMutex::new(1);
+    let mut node = McsNode::new();
+    mcs.lock_with(&mut node, |n| *n += 1);
+
+    let rwlock = SpinRwLock::new(1);
+    *SpinRwLockUpgradeableGuard::upgrade(rwlock.upgradeable_read()) += 1;
+
+    let raw = RawSpinLock::new();
+    drop(raw.lock());
+    let raw_released = !raw.is_locked();
+
+    let total = *spin.lock()
+        + *backoff.lock()
+        + *ticket.lock()
+        + mcs.lock_with(&mut node, |n| *n)
+        + *rwlock.read()
+        + *GLOBAL.lock();
+    if total == 11 && raw_released {
+        0
+    } else {
+        1
+    }
+}
+
+// Hosted targets ship a `core` built with unwinding, which still wants a personality routine even though with
+// `panic = "abort"` it never runs. A real embedded target doesn't need this.
+#[no_mangle]
+extern "C" fn rust_eh_personality() {}
+
+// `main` is called from libc's startup code, which nothing else pulls in without std
+#[link(name = "c")]
+extern "C" {}
+
+#[panic_handler]
+fn panic(_: &PanicInfo) -> ! {
+    loop {}
+}
@@ -0,0 +1,127 @@
+// A guard that owns an `Arc` of its mutex instead of borrowing it, so it has no lifetime and can be stored anywhere
+// the `Arc` could. Moving it to another thread still needs the `send_guard` feature, same as `SpinMutexGuard`.
+
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut, Drop};
+use core::ptr::NonNull;
+use std::sync::Arc;
+
+use crate::relax::{RelaxStrategy, Spin};
+use crate::sync::Access;
+use crate::{acquire, release, try_acquire, GuardMarker, SpinMutex};
+
+pub struct ArcSpinMutexGuard<T: ?Sized, R = Spin> {
+    mutex: Arc<SpinMutex<T, R>>,
+    // Points into `mutex`, which the `Arc` keeps alive and in place
+    data: NonNull<T>,
+    access: Access,
+    _marker: GuardMarker,
+}
+
+impl<T: ?Sized, R: RelaxStrategy> SpinMutex<T, R> {
+    /// Like `lock`, but the guard keeps its own clone of the `Arc` alive instead of borrowing the mutex.
+    pub fn lock_arc(self: &Arc<Self>) -> ArcSpinMutexGuard<T, R> {
+        #[cfg(feature = "deadlock-detection")]
+        if let Err(e) = crate::deadlock::check(&**self) {
+            panic!("{e}");
+        }
+        acquire::<R>(&self.raw.word);
+        ArcSpinMutexGuard::from(self.clone())
+    }
+
+    pub fn try_lock_arc(self: &Arc<Self>) -> Option<ArcSpinMutexGuard<T, R>> {
+        try_acquire(&self.raw.word).then(|| ArcSpinMutexGuard::from(self.clone()))
+    }
+}
+
+impl<T: ?Sized, R> ArcSpinMutexGuard<T, R> {
+    fn from(mutex: Arc<SpinMutex<T, R>>) -> Self {
+        #[cfg(feature = "deadlock-detection")]
+        crate::deadlock::acquired(&*mutex);
+        let (data, access) = unsafe { mutex.data.write() };
+        Self {
+            data: NonNull::from(data),
+            access,
+            mutex,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The mutex this guard is holding, e.g. to hand another clone of the `Arc` to someone who'll lock it later.
+    pub fn mutex(s: &Self) -> &Arc<SpinMutex<T, R>> {
+        &s.mutex
+    }
+}
+
+impl<T: ?Sized, R> Drop for ArcSpinMutexGuard<T, R> {
+    fn drop(&mut self) {
+        self.access.end();
+        release(&self.mutex.raw.word);
+    }
+}
+
+impl<T: ?Sized, R> Deref for ArcSpinMutexGuard<T, R> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.data.as_ref() }
+    }
+}
+
+impl<T: ?Sized, R> DerefMut for ArcSpinMutexGuard<T, R> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { self.data.as_mut() }
+    }
+}
+
+// The data pointer is what keeps these from being automatic. Both also hand out the `Arc` through `mutex`, and the
+// mutex itself is only `Sync` when `T: Send`, so that has to hold too.
+#[cfg(feature = "send_guard")]
+unsafe impl<T: ?Sized + Send, R> Send for ArcSpinMutexGuard<T, R> {}
+unsafe impl<T: ?Sized + Send + Sync, R> Sync for ArcSpinMutexGuard<T, R> {}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+
+    struct Session {
+        state: ArcSpinMutexGuard<Vec<&'static str>>,
+    }
+
+    #[test]
+    fn guard_outlives_every_borrow() {
+        let m = Arc::new(SpinMutex::new(Vec::new()));
+
+        // No borrow of `m` survives this block, the session holds the lock on its own
+        let mut session = {
+            let m = m.clone();
+            Session {
+                state: m.lock_arc(),
+            }
+        };
+        session.state.push("started");
+        assert!(m.try_lock().is_none());
+        assert!(m.try_lock_arc().is_none());
+        assert!(Arc::ptr_eq(&m, ArcSpinMutexGuard::mutex(&session.state)));
+
+        drop(session);
+        assert_eq!(vec!["started"], *m.try_lock_arc().unwrap());
+    }
+
+    #[test]
+    fn guard_keeps_mutex_alive() {
+        let m = Arc::new(SpinMutex::new(1));
+        let mut guard = m.lock_arc();
+        drop(m);
+        *guard += 1;
+        assert_eq!(2, *guard);
+    }
+
+    #[cfg(feature = "send_guard")]
+    #[test]
+    fn guard_moves_into_spawned_thread() {
+        let m = Arc::new(SpinMutex::new(0));
+        let mut guard = m.lock_arc();
+        std::thread::spawn(move || *guard += 1).join().unwrap();
+        assert_eq!(1, *m.lock());
+    }
+}
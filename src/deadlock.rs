@@ -0,0 +1,298 @@
+// Debug aid behind the `deadlock-detection` feature. Every thread keeps a list of the `SpinMutex`es it holds, which
+// catches relocking a mutex we already hold, and every blocking `lock` while holding others adds "held before" edges
+// to one global lock-order graph. Locking `b` while holding `a` when the graph already has a path from `b` to `a` means
+// some thread once took them the other way round, so two threads running those paths together could deadlock. That's
+// reported even if this particular run got lucky.
+//
+// Mutexes are identified by an id handed out the first time they're checked, not by address, since addresses get
+// reused once a mutex is moved or dropped. The flip side is that the graph never forgets a mutex, and a leaked guard's
+// entry is never taken out of the held list, which is fine for a debugging feature but not something to leave on in
+// production.
+//
+// Held mutexes are kept in one global list tagged with the thread that locked them, rather than in a thread-local, so
+// a guard released on another thread (with `send_guard`) still takes its entry with it. Until then the entry stays
+// with the locking thread.
+
+use core::any::type_name;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use std::vec::Vec;
+
+use crate::sync::current_thread_id;
+use crate::{LockWord, SpinMutex};
+
+/// Why `SpinMutex::lock_checked` refused to lock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeadlockError {
+    /// This thread already holds the mutex, so locking it again would spin forever.
+    Relock { mutex: MutexName },
+    /// Some thread has locked `held` while holding `mutex` before, and now we're locking `mutex` while holding `held`.
+    LockOrderInversion { mutex: MutexName, held: MutexName },
+}
+
+/// Names a mutex in error messages: its type and an id that's unique for the life of the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MutexName {
+    pub type_name: &'static str,
+    pub id: usize,
+}
+
+impl fmt::Display for MutexName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} #{}", self.type_name, self.id)
+    }
+}
+
+impl fmt::Display for DeadlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Relock { mutex } => {
+                write!(f, "deadlock: this thread already holds {mutex} and tried to lock it again")
+            }
+            Self::LockOrderInversion { mutex, held } => write!(
+                f,
+                "potential deadlock: locking {mutex} while holding {held}, but {held} has been locked while holding \
+                 {mutex} before"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DeadlockError {}
+
+// Not a loom atomic: like the id counter for `ReentrantSpinMutex`, it doesn't order anything the lock protects
+pub(crate) struct LockId(AtomicUsize);
+
+static NEXT_LOCK_ID: AtomicUsize = AtomicUsize::new(1);
+
+impl LockId {
+    pub(crate) const fn new() -> Self {
+        Self(AtomicUsize::new(0))
+    }
+
+    // `None` until the mutex has been locked through `SpinMutex`
+    fn assigned(&self) -> Option<usize> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            id => Some(id),
+        }
+    }
+
+    fn get(&self) -> usize {
+        let id = self.0.load(Ordering::Relaxed);
+        if id != 0 {
+            return id;
+        }
+        let fresh = NEXT_LOCK_ID.fetch_add(1, Ordering::Relaxed);
+        // Two threads might race to name the same mutex, whoever stores first wins
+        match self
+            .0
+            .compare_exchange(0, fresh, Ordering::Relaxed, Ordering::Relaxed)
+        {
+            Ok(_) => fresh,
+            Err(id) => id,
+        }
+    }
+}
+
+struct Held {
+    thread: usize,
+    name: MutexName,
+}
+
+static HELD: Mutex<Vec<Held>> = Mutex::new(Vec::new());
+
+// Edge `a -> b`: some thread blocked on `b` while holding `a`
+static LOCK_ORDER: Mutex<BTreeMap<usize, BTreeSet<usize>>> = Mutex::new(BTreeMap::new());
+
+fn name_of<T: ?Sized, R>(m: &SpinMutex<T, R>) -> MutexName {
+    MutexName {
+        type_name: type_name::<SpinMutex<T, R>>(),
+        id: m.raw.word.id.get(),
+    }
+}
+
+/// Called before blocking on `m`. Records the new lock-order edges if nothing is wrong.
+pub(crate) fn check<T: ?Sized, R>(m: &SpinMutex<T, R>) -> Result<(), DeadlockError> {
+    let mutex = name_of(m);
+    let thread = current_thread_id();
+    let held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+    let ours = || held.iter().filter(|h| h.thread == thread);
+    if ours().any(|h| h.name.id == mutex.id) {
+        return Err(DeadlockError::Relock { mutex });
+    }
+
+    let mut order = LOCK_ORDER.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(h) = ours().find(|h| reaches(&order, mutex.id, h.name.id)) {
+        return Err(DeadlockError::LockOrderInversion {
+            mutex,
+            held: h.name,
+        });
+    }
+    for h in ours() {
+        order.entry(h.name.id).or_default().insert(mutex.id);
+    }
+    Ok(())
+}
+
+fn reaches(order: &BTreeMap<usize, BTreeSet<usize>>, from: usize, to: usize) -> bool {
+    let mut seen = BTreeSet::new();
+    let mut stack = vec![from];
+    while let Some(id) = stack.pop() {
+        if id == to {
+            return true;
+        }
+        if seen.insert(id) {
+            stack.extend(order.get(&id).into_iter().flatten());
+        }
+    }
+    false
+}
+
+pub(crate) fn acquired<T: ?Sized, R>(m: &SpinMutex<T, R>) {
+    resume(Some(name_of(m)));
+}
+
+// Whichever thread the guard ended up on, only one entry can be for this mutex, so there's no need to ask who we are
+pub(crate) fn released(lock: &LockWord) {
+    suspend(lock);
+}
+
+/// Like `released`, but hands the entry back so `SpinMutexGuard::unlocked` can put it back once it has relocked.
+pub(crate) fn suspend(lock: &LockWord) -> Option<MutexName> {
+    // A `RawSpinLock` or some other lock that was never checked, nothing to look up
+    let id = lock.id.assigned()?;
+    let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+    let i = held.iter().position(|h| h.name.id == id)?;
+    Some(held.swap_remove(i).name)
+}
+
+pub(crate) fn resume(name: Option<MutexName>) {
+    if let Some(name) = name {
+        let thread = current_thread_id();
+        HELD.lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Held { thread, name });
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use crate::{DeadlockError, SpinMutex, SpinMutexGuard};
+
+    #[test]
+    fn relock_is_an_error_not_a_hang() {
+        let m = SpinMutex::new(0);
+        let guard = m.lock();
+        let Err(DeadlockError::Relock { mutex }) = m.lock_checked() else {
+            panic!("relocking should have been caught");
+        };
+        assert!(mutex.type_name.contains("SpinMutex<i32"));
+
+        // Failing without spinning is fine, that's not a deadlock
+        assert!(m.try_lock().is_none());
+        drop(guard);
+        assert!(m.lock_checked().is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "already holds")]
+    fn lock_panics_on_relock() {
+        let m = SpinMutex::new(0);
+        let _guard = m.lock();
+        let _again = m.lock();
+    }
+
+    #[test]
+    fn opposite_lock_order_is_reported() {
+        let a = SpinMutex::new(0);
+        let b = SpinMutex::new(0);
+        {
+            let _a = a.lock();
+            let _b = b.lock();
+        }
+        // Same order again is fine
+        {
+            let _a = a.lock();
+            let _b = b.lock();
+        }
+
+        let _b = b.lock();
+        let Err(DeadlockError::LockOrderInversion { mutex, held }) = a.lock_checked() else {
+            panic!("AB/BA should have been caught");
+        };
+        assert_eq!(a.raw.word.id.get(), mutex.id);
+        assert_eq!(b.raw.word.id.get(), held.id);
+    }
+
+    #[test]
+    fn inversion_through_a_longer_cycle() {
+        let a = SpinMutex::new(());
+        let b = SpinMutex::new(());
+        let c = SpinMutex::new(());
+        {
+            let _a = a.lock();
+            let _b = b.lock();
+        }
+        {
+            let _b = b.lock();
+            let _c = c.lock();
+        }
+
+        let _c = c.lock();
+        assert!(matches!(
+            a.lock_checked(),
+            Err(DeadlockError::LockOrderInversion { .. })
+        ));
+    }
+
+    #[test]
+    fn unlocked_and_mapped_guards_keep_tracking_straight() {
+        let m = SpinMutex::new((0, 0));
+        let mut guard = m.lock();
+        SpinMutexGuard::unlocked(&mut guard, || {
+            // Not ours while `f` runs, so this is allowed
+            m.lock().0 += 1;
+        });
+        assert!(matches!(
+            m.lock_checked(),
+            Err(DeadlockError::Relock { .. })
+        ));
+        drop(guard);
+
+        let mapped = SpinMutexGuard::map(m.lock(), |pair| &mut pair.1);
+        assert!(m.lock_checked().is_err());
+        drop(mapped);
+        assert_eq!((1, 0), *m.lock_checked().unwrap());
+    }
+
+    #[cfg(feature = "send_guard")]
+    #[test]
+    fn guard_released_on_another_thread_is_forgotten() {
+        let m = SpinMutex::new(0);
+        let guard = m.lock();
+        std::thread::scope(|s| {
+            s.spawn(move || drop(guard));
+        });
+        assert!(m.lock_checked().is_ok());
+    }
+
+    #[test]
+    fn leaked_guard_does_not_haunt_the_next_mutex_at_its_address() {
+        // Same slot every time round, so each mutex most likely reuses the last one's address
+        for _ in 0..3 {
+            let m = SpinMutex::new(0);
+            assert!(m.lock_checked().is_ok());
+            SpinMutexGuard::leak(m.lock());
+        }
+    }
+
+    #[test]
+    fn raw_locks_stay_out_of_the_held_list() {
+        let raw = crate::RawSpinLock::new();
+        drop(raw.lock());
+        assert_eq!(None, raw.word.id.assigned());
+    }
+}
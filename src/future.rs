@@ -0,0 +1,320 @@
+// Async acquisition for `SpinMutex`. A future that finds the lock taken parks its `Waker` in a global table keyed by the
+// mutex's address (the same trick parking_lot uses for threads), so `SpinMutex` itself doesn't grow a waiter list and
+// unlocking only pays for waking when the WAITERS bit says someone is actually parked.
+//
+// Every parked future on a mutex is woken at once when it unlocks; they race for it and the losers park again. Each
+// future's entry carries a token of its own, so one that's dropped before it gets the lock (a lost `select!`, a
+// timeout) can take its entry back out instead of leaving it for whatever mutex ends up at that address next.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::AtomicUsize;
+use core::task::{Context, Poll, Waker};
+use std::vec::Vec;
+
+use crate::raw::{LOCKED, WAITERS};
+use crate::relax::RelaxStrategy;
+use crate::sync::{AddrTable, AtomicU8, Ordering};
+use crate::{try_acquire, SpinMutex, SpinMutexGuard};
+
+// Keyed by the lock word's address
+static PARKED: AddrTable<Parked> = AddrTable::new();
+
+struct Parked {
+    token: usize,
+    waker: Waker,
+}
+
+// Not a loom atomic, like the id counters elsewhere it doesn't order anything
+static NEXT_TOKEN: AtomicUsize = AtomicUsize::new(0);
+
+fn addr(flag: &AtomicU8) -> usize {
+    flag as *const AtomicU8 as usize
+}
+
+fn park(flag: &AtomicU8, token: usize, waker: &Waker) {
+    let addr = addr(flag);
+    let mut parked = PARKED.bucket(addr);
+    // Polled again before being woken, e.g. by a `select!`, so just refresh the waker we already have
+    if let Some((_, existing)) = parked.iter_mut().find(|(_, p)| p.token == token) {
+        existing.waker.clone_from(waker);
+        return;
+    }
+    parked.push((
+        addr,
+        Parked {
+            token,
+            waker: waker.clone(),
+        },
+    ));
+}
+
+// Nothing to do if an unlock already took the entry out to wake it
+fn unpark(flag: &AtomicU8, token: usize) {
+    let mut parked = PARKED.bucket(addr(flag));
+    if let Some(i) = parked.iter().position(|(_, p)| p.token == token) {
+        parked.swap_remove(i);
+    }
+}
+
+pub(crate) fn wake_all(flag: &AtomicU8) {
+    let addr = addr(flag);
+    let woken: Vec<Waker> = {
+        let mut parked = PARKED.bucket(addr);
+        let mut woken = Vec::new();
+        parked.retain(|(a, p)| {
+            if *a == addr {
+                woken.push(p.waker.clone());
+                false
+            } else {
+                true
+            }
+        });
+        woken
+    };
+    // Outside the bucket lock, a waker is free to do whatever it likes, including polling right away
+    for waker in woken {
+        waker.wake();
+    }
+}
+
+impl<'a, T: ?Sized, R: RelaxStrategy> SpinMutex<T, R> {
+    /// Resolves to the guard once the lock is free, parking the task in between instead of spinning inside `poll`.
+    ///
+    /// Note the guard is only `Send` with the `send_guard` feature, so holding it across an `.await` on a
+    /// work-stealing executor needs that feature too.
+    ///
+    /// With `deadlock-detection`, this panics up front where `lock` would, instead of staying `Pending` forever. The
+    /// detection goes by thread, not by task, so waiting on a mutex that another task on the same thread holds counts
+    /// as a relock too.
+    pub fn lock_async(&'a self) -> SpinMutexLockFuture<'a, T, R> {
+        // Checked up front like `lock` does, a relock would otherwise just stay `Pending` forever
+        #[cfg(feature = "deadlock-detection")]
+        if let Err(e) = crate::deadlock::check(self) {
+            panic!("{e}");
+        }
+        SpinMutexLockFuture {
+            mutex: self,
+            token: None,
+        }
+    }
+}
+
+pub struct SpinMutexLockFuture<'a, T: ?Sized, R> {
+    mutex: &'a SpinMutex<T, R>,
+    // Handed out the first time we park
+    token: Option<usize>,
+}
+
+impl<'a, T: ?Sized, R: RelaxStrategy> Future for SpinMutexLockFuture<'a, T, R> {
+    type Output = SpinMutexGuard<'a, T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mutex = self.mutex;
+        loop {
+            if try_acquire(&mutex.raw.word) {
+                // We may have got in without being woken, in which case our entry is still parked
+                if let Some(token) = self.token.take() {
+                    unpark(&mutex.raw.word, token);
+                }
+                return Poll::Ready(SpinMutexGuard::from(mutex));
+            }
+
+            let token = *self.token.get_or_insert_with(|| {
+                NEXT_TOKEN.fetch_add(1, core::sync::atomic::Ordering::Relaxed)
+            });
+            park(&mutex.raw.word, token, cx.waker());
+            // Only now that the waker is parked is it safe to tell the holder to look for it. If the lock turns out to
+            // have been released in the meantime, nobody is going to wake us, so go around and try again.
+            if mutex.raw.word.fetch_or(WAITERS, Ordering::AcqRel) & LOCKED != 0 {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+impl<T: ?Sized, R> Drop for SpinMutexLockFuture<'_, T, R> {
+    fn drop(&mut self) {
+        if let Some(token) = self.token {
+            unpark(&self.mutex.raw.word, token);
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::{mpsc, Arc};
+    use std::task::Wake;
+    use std::thread::{self, Thread};
+
+    // Just enough executor to drive a future to completion on the current thread
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn uncontended_is_ready_immediately() {
+        let m = SpinMutex::new(1);
+        *block_on(m.lock_async()) += 1;
+        assert_eq!(2, *m.lock());
+    }
+
+    #[cfg(feature = "deadlock-detection")]
+    #[test]
+    #[should_panic(expected = "already holds")]
+    fn relock_panics_instead_of_pending_forever() {
+        let m = SpinMutex::new(0);
+        let _guard = m.lock();
+        let _never = m.lock_async();
+    }
+
+    // Holds `m` on another thread until `unlock`. Holding it on this thread and then polling a future for it here
+    // would look like a relock to deadlock detection, which goes by thread, not by task.
+    struct HeldElsewhere {
+        release: mpsc::Sender<()>,
+        holder: thread::JoinHandle<()>,
+    }
+
+    impl HeldElsewhere {
+        fn new(m: &Arc<SpinMutex<i32>>) -> Self {
+            let (locked, is_locked) = mpsc::channel();
+            let (release, released) = mpsc::channel::<()>();
+            let m = m.clone();
+            let holder = thread::spawn(move || {
+                let _guard = m.lock();
+                locked.send(()).unwrap();
+                // Returns once `release` is dropped
+                let _ = released.recv();
+            });
+            is_locked.recv().unwrap();
+            Self { release, holder }
+        }
+
+        fn unlock(self) {
+            drop(self.release);
+            self.holder.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn unlock_wakes_parked_future() {
+        let m = Arc::new(SpinMutex::new(0));
+        let holder = HeldElsewhere::new(&m);
+
+        let counter = Arc::new(CountingWaker::default());
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = m.lock_async();
+
+        assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+        // Polling again while still locked mustn't park a second copy
+        assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+        assert_eq!(0, counter.0.load(std::sync::atomic::Ordering::Relaxed));
+
+        holder.unlock();
+        assert_eq!(1, counter.0.load(std::sync::atomic::Ordering::Relaxed));
+        assert!(Pin::new(&mut future).poll(&mut cx).is_ready());
+    }
+
+    fn parked_on(flag: &AtomicU8) -> usize {
+        let addr = addr(flag);
+        let parked = PARKED.bucket(addr);
+        parked.iter().filter(|(a, _)| *a == addr).count()
+    }
+
+    #[test]
+    fn dropped_future_unparks() {
+        let m = Arc::new(SpinMutex::new(0));
+        let holder = HeldElsewhere::new(&m);
+
+        let counter = Arc::new(CountingWaker::default());
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut cancelled = m.lock_async();
+        let mut kept = m.lock_async();
+        assert!(Pin::new(&mut cancelled).poll(&mut cx).is_pending());
+        assert!(Pin::new(&mut kept).poll(&mut cx).is_pending());
+        // Same waker, but still two entries
+        assert_eq!(2, parked_on(&m.raw.word));
+
+        drop(cancelled);
+        assert_eq!(1, parked_on(&m.raw.word));
+        holder.unlock();
+        assert_eq!(1, counter.0.load(std::sync::atomic::Ordering::Relaxed));
+        assert!(Pin::new(&mut kept).poll(&mut cx).is_ready());
+        assert_eq!(0, parked_on(&m.raw.word));
+    }
+
+    #[test]
+    fn ready_without_wake_unparks() {
+        let m = Arc::new(SpinMutex::new(0));
+        let holder = HeldElsewhere::new(&m);
+        let counter = Arc::new(CountingWaker::default());
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = m.lock_async();
+        assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+
+        // Hide the waiter from the unlock, as if we'd raced it
+        m.raw.word.fetch_and(!WAITERS, Ordering::Relaxed);
+        holder.unlock();
+        assert_eq!(0, counter.0.load(std::sync::atomic::Ordering::Relaxed));
+        assert!(Pin::new(&mut future).poll(&mut cx).is_ready());
+        assert_eq!(0, parked_on(&m.raw.word));
+    }
+
+    #[test]
+    fn async_and_sync_lockers_mix() {
+        const THREADS: usize = 4;
+        const ITERATIONS: usize = 500;
+
+        let m = Arc::new(SpinMutex::new(0));
+        let handles: Vec<_> = (0..THREADS)
+            .map(|i| {
+                let m = m.clone();
+                thread::spawn(move || {
+                    for _ in 0..ITERATIONS {
+                        if i % 2 == 0 {
+                            *block_on(m.lock_async()) += 1;
+                        } else {
+                            *m.lock() += 1;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(THREADS * ITERATIONS, *m.lock());
+        assert!(!m.is_locked());
+    }
+}
@@ -0,0 +1,312 @@
+// Spins for a bit like `SpinMutex`, then gives up and sleeps in the kernel until the holder wakes it. This is the
+// three-state futex mutex from Drepper's "Futexes Are Tricky":
+//
+//   0 = unlocked, 1 = locked and nobody asleep, 2 = locked and someone may be asleep
+//
+// so an unlock only makes a syscall when the word says there's someone to wake.
+//
+// The lock word has to be a real `AtomicU32` the kernel can look at, so this type goes around the loom shim and isn't
+// part of the loom models.
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut, Drop};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::relax::{RelaxStrategy, Spin};
+use crate::GuardMarker;
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+const CONTENDED: u32 = 2;
+
+// Roughly what std's own futex mutex spins for before sleeping
+const SPIN_LIMIT: usize = 100;
+
+#[cfg(target_os = "linux")]
+use futex as parker;
+#[cfg(not(target_os = "linux"))]
+use thread_park as parker;
+
+#[cfg(target_os = "linux")]
+mod futex {
+    use core::ptr;
+    use core::sync::atomic::AtomicU32;
+
+    /// Sleeps as long as `word` still holds `expected`. May return early for no reason, callers re-check.
+    pub(super) fn wait(word: &AtomicU32, expected: u32) {
+        // The kernel compares `word` against `expected` atomically with going to sleep, so a wake that lands between
+        // our last look and this call isn't lost. EINTR and EAGAIN just mean "look again", which the caller does.
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                word.as_ptr(),
+                libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+                expected,
+                ptr::null::<libc::timespec>(),
+            );
+        }
+    }
+
+    pub(super) fn wake_one(word: &AtomicU32) {
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                word.as_ptr(),
+                libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+                1,
+            );
+        }
+    }
+}
+
+// Portable stand-in for the futex: sleeping threads are kept in a global table keyed by the lock word's address, and
+// checking the word happens under the bucket's lock so a wake can't slip in between the check and the sleep.
+#[cfg(any(not(target_os = "linux"), test))]
+#[cfg_attr(target_os = "linux", allow(dead_code))] // only the tests use it here
+mod thread_park {
+    use core::sync::atomic::{AtomicU32, Ordering};
+    use std::thread::{self, Thread};
+
+    use crate::sync::AddrTable;
+
+    static SLEEPING: AddrTable<Thread> = AddrTable::new();
+
+    pub(super) fn wait(word: &AtomicU32, expected: u32) {
+        let addr = word.as_ptr() as usize;
+        let me = thread::current();
+        {
+            let mut sleeping = SLEEPING.bucket(addr);
+            if word.load(Ordering::Relaxed) != expected {
+                return;
+            }
+            sleeping.push((addr, me.clone()));
+        }
+
+        thread::park();
+
+        // A spurious unpark leaves our entry behind, and a later `wake_one` spent on a thread that isn't asleep any
+        // more would be a lost wakeup for whoever is, so take it out ourselves
+        let mut sleeping = SLEEPING.bucket(addr);
+        if let Some(i) = sleeping
+            .iter()
+            .position(|(a, t)| *a == addr && t.id() == me.id())
+        {
+            sleeping.swap_remove(i);
+        }
+    }
+
+    pub(super) fn wake_one(word: &AtomicU32) {
+        let addr = word.as_ptr() as usize;
+        let woken = {
+            let mut sleeping = SLEEPING.bucket(addr);
+            sleeping
+                .iter()
+                .position(|(a, _)| *a == addr)
+                .map(|i| sleeping.swap_remove(i).1)
+        };
+        if let Some(thread) = woken {
+            thread.unpark();
+        }
+    }
+}
+
+pub struct HybridMutex<T, R = Spin> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+    relax: PhantomData<R>,
+}
+
+impl<T> HybridMutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self::with_relax(data)
+    }
+}
+
+impl<'a, T, R: RelaxStrategy> HybridMutex<T, R> {
+    pub const fn with_relax(data: T) -> Self {
+        Self {
+            state: AtomicU32::new(UNLOCKED),
+            data: UnsafeCell::new(data),
+            relax: PhantomData,
+        }
+    }
+
+    pub fn lock(&'a self) -> HybridMutexGuard<'a, T> {
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.lock_contended();
+        }
+        HybridMutexGuard::from(self)
+    }
+
+    pub fn try_lock(&'a self) -> Option<HybridMutexGuard<'a, T>> {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| HybridMutexGuard::from(self))
+    }
+
+    #[cold]
+    fn lock_contended(&self) {
+        let mut relax = R::default();
+        let mut state = self.state.load(Ordering::Relaxed);
+        for _ in 0..SPIN_LIMIT {
+            // Once someone is asleep, the holder is clearly taking its time, so don't bother spinning
+            if state == CONTENDED {
+                break;
+            }
+            if state == UNLOCKED {
+                match self.state.compare_exchange(
+                    UNLOCKED,
+                    LOCKED,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return,
+                    Err(now) => state = now,
+                }
+                continue;
+            }
+            relax.relax();
+            state = self.state.load(Ordering::Relaxed);
+        }
+
+        // From here on we always leave the word at CONTENDED, even when we end up grabbing a free lock. We can't tell
+        // whether others are still asleep, and a spare wake syscall beats a thread that never gets woken.
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            parker::wait(&self.state, CONTENDED);
+        }
+    }
+}
+
+pub struct HybridMutexGuard<'a, T> {
+    state: &'a AtomicU32,
+    data: &'a mut T,
+    _marker: GuardMarker,
+}
+
+impl<'a, T> HybridMutexGuard<'a, T> {
+    pub(crate) fn from<R>(m: &'a HybridMutex<T, R>) -> Self {
+        Self {
+            state: &m.state,
+            data: unsafe { &mut *m.data.get() },
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T> Drop for HybridMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            parker::wake_one(self.state);
+        }
+    }
+}
+
+impl<'a, T> Deref for HybridMutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+impl<'a, T> DerefMut for HybridMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.data
+    }
+}
+
+unsafe impl<T: Send, R> Send for HybridMutex<T, R> {}
+unsafe impl<T: Send, R> Sync for HybridMutex<T, R> {}
+
+unsafe impl<T: Sync> Sync for HybridMutexGuard<'_, T> {}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread::{sleep, spawn as thread_spawn};
+    use std::time::Duration;
+
+    #[test]
+    fn uncontended_never_marks_contended() {
+        let m = HybridMutex::new(0);
+        *m.lock() += 1;
+        assert_eq!(UNLOCKED, m.state.load(Ordering::Relaxed));
+
+        let guard = m.try_lock().unwrap();
+        assert_eq!(LOCKED, m.state.load(Ordering::Relaxed));
+        assert!(m.try_lock().is_none());
+        drop(guard);
+        assert_eq!(1, *m.lock());
+    }
+
+    #[test]
+    fn long_hold_puts_waiter_to_sleep_and_wakes_it() {
+        let m = Arc::new(HybridMutex::new(0));
+        let guard = m.lock();
+
+        let m2 = m.clone();
+        let waiter = thread_spawn(move || *m2.lock() += 1);
+
+        // Long past the spin phase, the waiter has to have gone to sleep by now
+        while m.state.load(Ordering::Relaxed) != CONTENDED {
+            sleep(Duration::from_millis(1));
+        }
+        drop(guard);
+
+        waiter.join().unwrap();
+        assert_eq!(1, *m.lock());
+    }
+
+    #[test]
+    fn many_threads_with_long_critical_sections() {
+        const THREADS: usize = 8;
+        const ITERATIONS: usize = 50;
+
+        let m = Arc::new(HybridMutex::new(0));
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let m = m.clone();
+                thread_spawn(move || {
+                    for _ in 0..ITERATIONS {
+                        let mut guard = m.lock();
+                        // Long enough that the others give up spinning and park
+                        sleep(Duration::from_micros(100));
+                        *guard += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(THREADS * ITERATIONS, *m.lock());
+        assert_eq!(UNLOCKED, m.state.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn thread_park_fallback_wait_and_wake() {
+        let word = Arc::new(AtomicU32::new(CONTENDED));
+
+        // Not the expected value, must not sleep at all
+        thread_park::wait(&word, LOCKED);
+
+        let word2 = word.clone();
+        let sleeper = thread_spawn(move || {
+            while word2.load(Ordering::Acquire) == CONTENDED {
+                thread_park::wait(&word2, CONTENDED);
+            }
+        });
+
+        sleep(Duration::from_millis(50));
+        word.store(UNLOCKED, Ordering::Release);
+        thread_park::wake_one(&word);
+        sleeper.join().unwrap();
+    }
+}
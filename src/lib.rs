@@ -1,74 +1,319 @@
-use std::cell::UnsafeCell;
-use std::ops::{Deref, DerefMut, Drop};
-use std::sync::atomic::{AtomicBool, Ordering};
+// Only needs `core` unless the `std` feature is on. Tests always link std, the harness needs it, but the std-only APIs
+// still follow the feature so the no_std paths get tested too.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut, Drop};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "std")]
+pub mod arc_guard;
+#[cfg(feature = "deadlock-detection")]
+pub mod deadlock;
+#[cfg(feature = "async")]
+pub mod future;
+#[cfg(feature = "std")]
+pub mod hybrid;
+pub mod mapped;
+pub mod mcs;
+pub mod padded;
+#[cfg(feature = "std")]
+pub mod poison;
+pub mod raw;
+#[cfg(feature = "std")]
+pub mod reentrant;
+pub mod relax;
+pub mod rwlock;
+#[cfg(feature = "std")]
+pub mod sharded;
+#[cfg(feature = "stats")]
+pub mod stats;
+mod sync;
+pub mod ticket;
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
+#[cfg(feature = "std")]
+pub use arc_guard::ArcSpinMutexGuard;
+#[cfg(feature = "deadlock-detection")]
+pub use deadlock::{DeadlockError, MutexName};
+#[cfg(feature = "async")]
+pub use future::SpinMutexLockFuture;
+#[cfg(feature = "std")]
+pub use hybrid::{HybridMutex, HybridMutexGuard};
+pub use mapped::MappedSpinMutexGuard;
+pub use mcs::{McsMutex, McsMutexGuard, McsNode};
+pub use padded::{CachePadded, PaddedSpinMutex};
+#[cfg(feature = "std")]
+pub use poison::{
+    LockResult, PoisonError, PoisonSpinMutex, PoisonSpinMutexGuard, TryLockError, TryLockResult,
+};
+#[cfg(feature = "std")]
+pub(crate) use raw::try_acquire_until;
+pub(crate) use raw::{acquire, hang_back, release, try_acquire, LockWord};
+pub use raw::{RawSpinLock, RawSpinLockGuard};
+#[cfg(feature = "std")]
+pub use reentrant::{ReentrantSpinMutex, ReentrantSpinMutexGuard};
+pub use relax::{Backoff, RelaxStrategy, Spin};
+#[cfg(feature = "std")]
+pub use relax::{SpinThenYield, Yield};
+pub use rwlock::{
+    SpinRwLock, SpinRwLockReadGuard, SpinRwLockUpgradeableGuard, SpinRwLockWriteGuard,
+};
+#[cfg(feature = "std")]
+pub use sharded::{ShardEntry, SpinShardedMap};
+#[cfg(feature = "stats")]
+pub use stats::LockStats;
+use sync::{const_fn, Access, UnsafeCell};
+pub use ticket::{TicketMutex, TicketMutexGuard};
+#[cfg(feature = "watchdog")]
+pub use watchdog::{clear_spin_watchdog, set_spin_watchdog, SpinThreshold, StuckLock};
 
 pub use SpinMutex as Mutex;
-pub struct SpinMutex<T> {
-    pub(crate) lock: AtomicBool,
+pub struct SpinMutex<T: ?Sized, R = Spin> {
+    pub(crate) raw: RawSpinLock<R>,
+    // Has to stay the last field for `SpinMutex<[T; N]>` -> `SpinMutex<[T]>` style unsizing to work
     pub(crate) data: UnsafeCell<T>,
 }
 
-impl<'a, T> SpinMutex<T> {
-    pub fn new(data: T) -> Self {
-        Self {
-            data: UnsafeCell::new(data),
-            lock: AtomicBool::new(false),
+// `new` only exists for the default strategy, same trick as `HashMap::new`, otherwise `SpinMutex::new(0)` couldn't
+// infer `R`
+impl<T> SpinMutex<T> {
+    const_fn! {
+        pub fn new(data: T) -> Self {
+            Self::with_relax(data)
         }
     }
+}
 
-    pub fn lock(&'a self) -> SpinMutexGuard<'a, T> {
-        while !self
-            .lock
-            .compare_exchange(false, true, Ordering::Release, Ordering::Relaxed)
-            .is_ok_and(|v| v == true)
-        {
-            std::hint::spin_loop();
+impl<T, R: RelaxStrategy> SpinMutex<T, R> {
+    const_fn! {
+        /// Like `new`, but waiters relax using `R`, e.g. `SpinMutex::<_, Backoff>::with_relax(0)`.
+        pub fn with_relax(data: T) -> Self {
+            Self {
+                data: UnsafeCell::new(data),
+                raw: RawSpinLock::with_relax(),
+            }
         }
+    }
+
+    /// No locking needed, owning the mutex means nobody else can be holding it.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
 
+impl<T: ?Sized, R> SpinMutex<T, R> {
+    /// No locking needed, `&mut self` already proves nobody else can get at the data.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Raw pointer to the data, for when you're managing exclusion yourself. Dereferencing it while anyone else might
+    /// hold the lock is on you.
+    pub fn data_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+
+    /// Only a snapshot, the answer can be stale before you get to act on it. Fine for diagnostics, not for deciding
+    /// whether it's safe to touch the data.
+    pub fn is_locked(&self) -> bool {
+        self.raw.is_locked()
+    }
+}
+
+impl<'a, T: ?Sized, R: RelaxStrategy> SpinMutex<T, R> {
+    pub fn lock(&'a self) -> SpinMutexGuard<'a, T> {
+        #[cfg(feature = "deadlock-detection")]
+        if let Err(e) = deadlock::check(self) {
+            panic!("{e}");
+        }
+        acquire::<R>(&self.raw.word);
         SpinMutexGuard::from(self)
     }
+
+    /// Like `lock`, but returns an error instead of panicking if locking here could deadlock: because this thread
+    /// already holds the mutex, or because it has been locked in the opposite order to one we're holding before.
+    #[cfg(feature = "deadlock-detection")]
+    pub fn lock_checked(&'a self) -> Result<SpinMutexGuard<'a, T>, deadlock::DeadlockError> {
+        deadlock::check(self)?;
+        acquire::<R>(&self.raw.word);
+        Ok(SpinMutexGuard::from(self))
+    }
+
+    /// Makes a single attempt at taking the lock, never spinning.
+    pub fn try_lock(&'a self) -> Option<SpinMutexGuard<'a, T>> {
+        try_acquire(&self.raw.word).then(|| SpinMutexGuard::from(self))
+    }
+
+    /// Spins for at most `timeout` before giving up.
+    #[cfg(feature = "std")]
+    pub fn try_lock_for(&'a self, timeout: Duration) -> Option<SpinMutexGuard<'a, T>> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.try_lock_until(deadline),
+            // A deadline that far out can't be represented, so it might as well be forever
+            None => Some(self.lock()),
+        }
+    }
+
+    /// Spins until `deadline` before giving up. Always makes at least one attempt, even if `deadline` has already passed.
+    #[cfg(feature = "std")]
+    pub fn try_lock_until(&'a self, deadline: Instant) -> Option<SpinMutexGuard<'a, T>> {
+        try_acquire_until::<R>(&self.raw.word, deadline).then(|| SpinMutexGuard::from(self))
+    }
 }
 
-pub struct SpinMutexGuard<'a, T> {
-    lock: &'a AtomicBool,
+impl<T: Default, R: RelaxStrategy> Default for SpinMutex<T, R> {
+    fn default() -> Self {
+        Self::with_relax(T::default())
+    }
+}
+
+impl<T, R: RelaxStrategy> From<T> for SpinMutex<T, R> {
+    fn from(data: T) -> Self {
+        Self::with_relax(data)
+    }
+}
+
+/// Declares `static` `SpinMutex`es without spelling out the mutex type:
+///
+/// ```
+/// my_mutex_learning::static_mutex! {
+///     static COUNTER: u64 = 0;
+///     pub(crate) static NAMES: Vec<&'static str> = Vec::new();
+/// }
+///
+/// *COUNTER.lock() += 1;
+/// NAMES.lock().push("me");
+/// ```
+#[macro_export]
+macro_rules! static_mutex {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)*) => {
+        $(
+            $(#[$attr])*
+            $vis static $name: $crate::SpinMutex<$ty> = $crate::SpinMutex::new($init);
+        )*
+    };
+}
+
+// A raw pointer is neither `Send` nor `Sync`, which keeps the guard on the thread that locked it unless
+// `send_guard` is enabled
+#[cfg(not(feature = "send_guard"))]
+pub(crate) type GuardMarker = PhantomData<*const ()>;
+#[cfg(feature = "send_guard")]
+pub(crate) type GuardMarker = PhantomData<()>;
+
+pub struct SpinMutexGuard<'a, T: ?Sized> {
+    lock: &'a LockWord,
     data: &'a mut T,
+    access: Access,
+    // `acquire` with the mutex's own relax strategy, for `unlocked` to take the lock back with. A plain fn pointer
+    // rather than another type parameter, so every guard stays a `SpinMutexGuard<'a, T>`.
+    relock: fn(&LockWord),
+    _marker: GuardMarker,
 }
 
-impl<'a, T> SpinMutexGuard<'a, T> {
-    pub(crate) fn from(m: &'a SpinMutex<T>) -> Self {
+impl<'a, T: ?Sized> SpinMutexGuard<'a, T> {
+    pub(crate) fn from<R: RelaxStrategy>(m: &'a SpinMutex<T, R>) -> Self {
+        #[cfg(feature = "deadlock-detection")]
+        deadlock::acquired(m);
+        let (data, access) = unsafe { m.data.write() };
         Self {
-            lock: &m.lock,
-            data: unsafe { &mut *m.data.get() },
+            lock: &m.raw.word,
+            data,
+            access,
+            relock: acquire::<R>,
+            _marker: PhantomData,
         }
     }
+
+    /// Same as dropping the guard, just easier to spot when reading the code.
+    pub fn unlock(s: Self) {
+        drop(s);
+    }
+
+    /// Gives up the guard but never releases the lock, so the data stays ours for as long as the mutex lives.
+    pub fn leak(s: Self) -> &'a mut T {
+        let (_, data, access) = s.into_parts();
+        // Still ours, so as far as loom is concerned we're never done with it either
+        access.leak();
+        data
+    }
+
+    /// Releases the lock while `f` runs and takes it back afterwards, even if `f` panics.
+    pub fn unlocked<F, U>(s: &mut Self, f: F) -> U
+    where
+        F: FnOnce() -> U,
+    {
+        // If `f` unwinds, the guard is dropped and releases the lock, so it had better be ours again by then
+        struct Relock<'b> {
+            lock: &'b LockWord,
+            relock: fn(&LockWord),
+            access: &'b mut Access,
+            #[cfg(feature = "deadlock-detection")]
+            held: Option<deadlock::MutexName>,
+        }
+        impl Drop for Relock<'_> {
+            fn drop(&mut self) {
+                (self.relock)(self.lock);
+                self.access.resume();
+                #[cfg(feature = "deadlock-detection")]
+                deadlock::resume(self.held.take());
+            }
+        }
+
+        s.access.end();
+        let lock = s.lock;
+        let _relock = Relock {
+            lock,
+            relock: s.relock,
+            access: &mut s.access,
+            #[cfg(feature = "deadlock-detection")]
+            held: deadlock::suspend(lock),
+        };
+        release(lock);
+        f()
+    }
+
+    /// Briefly releases the lock so anyone waiting on it gets a turn, then takes it back.
+    ///
+    /// Without this, a thread that unlocks and immediately relocks almost always wins, since it already owns the
+    /// cache line. So after releasing we hang back for a few spins, or until somebody else has taken the lock.
+    pub fn bump(s: &mut Self) {
+        let lock = s.lock;
+        Self::unlocked(s, || hang_back(lock));
+    }
 }
 
-impl<'a, T> Drop for SpinMutexGuard<'a, T> {
+impl<'a, T: ?Sized> Drop for SpinMutexGuard<'a, T> {
     fn drop(&mut self) {
-        self.lock.store(false, Ordering::Release);
+        self.access.end();
+        release(self.lock);
     }
 }
 
-impl<'a, T> Deref for SpinMutexGuard<'a, T> {
+impl<'a, T: ?Sized> Deref for SpinMutexGuard<'a, T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
         self.data
     }
 }
 
-impl<'a, T> DerefMut for SpinMutexGuard<'a, T> {
+impl<'a, T: ?Sized> DerefMut for SpinMutexGuard<'a, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.data
+        self.data
     }
 }
 
-unsafe impl<T> Send for SpinMutex<T> {}
-unsafe impl<T> Sync for SpinMutex<T> {}
+// Same bounds as `std::sync::Mutex`: the lock hands out `&mut T` to one thread at a time, so `T` only ever has to be
+// sent between threads, never shared
+unsafe impl<T: ?Sized + Send, R> Send for SpinMutex<T, R> {}
+unsafe impl<T: ?Sized + Send, R> Sync for SpinMutex<T, R> {}
 
-unsafe impl<'a, T> Send for SpinMutexGuard<'a, T> {}
-unsafe impl<'a, T> Sync for SpinMutexGuard<'a, T> {}
+// Sharing `&SpinMutexGuard` only gives out `&T`. `Send` is left to `GuardMarker`.
+unsafe impl<T: ?Sized + Sync> Sync for SpinMutexGuard<'_, T> {}
 
-#[cfg(test)]
+#[cfg(all(test, not(feature = "loom")))]
 mod tests {
     use super::*;
     use std::mem::MaybeUninit;
@@ -78,6 +323,27 @@ mod tests {
 
     const SLEEP_TIME: Duration = Duration::from_millis(100);
 
+    std::thread_local! {
+        static RELAXED: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    // Counts how often this thread had to relax, i.e. how many attempts at the lock failed before the last one
+    #[derive(Default)]
+    struct Counting;
+
+    impl Counting {
+        fn relaxed() -> usize {
+            RELAXED.with(|n| n.get())
+        }
+    }
+
+    impl RelaxStrategy for Counting {
+        fn relax(&mut self) {
+            RELAXED.with(|n| n.set(n.get() + 1));
+            std::thread::yield_now();
+        }
+    }
+
     #[test]
     fn it_works() {
         let m = Arc::new(Mutex::new(0));
@@ -97,5 +363,268 @@ mod tests {
             let mut guard = m2.lock();
             *guard = 5;
         }));
+
+        for handle in join_handles {
+            // SAFETY: both slots were written above
+            unsafe { handle.assume_init() }.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn try_lock_uncontended() {
+        let m = Mutex::new(1);
+        {
+            let mut guard = m.try_lock().expect("nobody else holds the lock");
+            *guard += 1;
+        }
+        assert_eq!(2, *m.try_lock().unwrap());
+    }
+
+    #[test]
+    fn try_lock_contended() {
+        let m = Arc::new(Mutex::new(()));
+        let guard = m.lock();
+
+        let m2 = m.clone();
+        let got_lock = thread_spawn(move || m2.try_lock().is_some())
+            .join()
+            .unwrap();
+        assert!(!got_lock);
+
+        drop(guard);
+        assert!(m.try_lock().is_some());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn try_lock_for_times_out() {
+        let m = Mutex::new(());
+        let _guard = m.lock();
+
+        let start = Instant::now();
+        assert!(m.try_lock_for(SLEEP_TIME).is_none());
+        assert!(start.elapsed() >= SLEEP_TIME);
+
+        // Deadline already passed, but we should still have tried exactly once: never relaxing means no retries
+        let m = SpinMutex::<_, Counting>::with_relax(());
+        let guard = m.lock();
+        assert!(m.try_lock_until(start).is_none());
+        assert_eq!(0, Counting::relaxed());
+        drop(guard);
+        assert!(m.try_lock_until(start).is_some());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn try_lock_for_succeeds_once_released() {
+        let m = Arc::new(Mutex::new(0));
+        let guard = m.lock();
+
+        let m2 = m.clone();
+        let waiter = thread_spawn(move || {
+            let mut guard = m2.try_lock_for(Duration::from_secs(10)).unwrap();
+            *guard += 1;
+        });
+
+        sleep(SLEEP_TIME);
+        drop(guard);
+        waiter.join().unwrap();
+        assert_eq!(1, *m.lock());
+    }
+
+    #[test]
+    fn ownership_apis_skip_the_lock() {
+        let mut m = Mutex::new(vec![1]);
+        m.get_mut().push(2);
+        assert!(!m.is_locked());
+
+        let guard = m.lock();
+        assert!(m.is_locked());
+        // Reading through `data_ptr` is fine here, we're the ones holding the lock
+        assert_eq!(2, unsafe { (*m.data_ptr()).len() });
+        drop(guard);
+
+        assert_eq!(vec![1, 2], m.into_inner());
+    }
+
+    #[test]
+    fn unsized_through_coercion() {
+        let slice: Box<Mutex<[u8]>> = Box::new(Mutex::new([1, 2, 3]));
+        slice.lock()[0] = 4;
+        assert_eq!([4, 2, 3], *slice.lock());
+
+        let shown: &Mutex<dyn std::fmt::Display> = &Mutex::new(5);
+        assert_eq!("5", shown.lock().to_string());
+
+        let arc: Arc<Mutex<dyn Fn() -> i32 + Send>> = Arc::new(Mutex::new(|| 7));
+        assert_eq!(7, (arc.lock())());
+    }
+
+    #[test]
+    fn unlock_and_leak() {
+        let m = Mutex::new(0);
+        SpinMutexGuard::unlock(m.lock());
+        assert!(!m.is_locked());
+
+        let data: &mut i32 = SpinMutexGuard::leak(m.lock());
+        *data = 1;
+        assert!(m.try_lock().is_none());
+    }
+
+    #[test]
+    fn unlocked_lets_others_in() {
+        let m = Arc::new(Mutex::new(0));
+        let mut guard = m.lock();
+        *guard = 1;
+
+        let m2 = m.clone();
+        let seen = SpinMutexGuard::unlocked(&mut guard, || {
+            thread_spawn(move || {
+                let mut guard = m2.lock();
+                *guard += 1;
+                *guard
+            })
+            .join()
+            .unwrap()
+        });
+        assert_eq!(2, seen);
+        assert_eq!(2, *guard);
+        assert!(m.is_locked());
+    }
+
+    #[test]
+    fn unlocked_relocks_on_panic() {
+        let m = Mutex::new(0);
+        let mut guard = m.lock();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            SpinMutexGuard::unlocked(&mut guard, || panic!("inside unlocked"))
+        }));
+        assert!(result.is_err());
+        assert!(m.is_locked());
+
+        drop(guard);
+        assert!(!m.is_locked());
+    }
+
+    #[test]
+    fn unlocked_relocks_with_the_mutexs_own_strategy() {
+        let m = Arc::new(SpinMutex::<_, Counting>::with_relax(0));
+        let mut guard = m.lock();
+        // Someone else holds the lock when `f` returns, so taking it back has to wait for them
+        let holder = SpinMutexGuard::unlocked(&mut guard, || {
+            let m2 = m.clone();
+            let holder = thread_spawn(move || {
+                let mut guard = m2.lock();
+                sleep(SLEEP_TIME);
+                *guard += 1;
+            });
+            while !m.is_locked() {
+                std::thread::yield_now();
+            }
+            holder
+        });
+        assert_eq!(1, *guard);
+        assert!(Counting::relaxed() > 0);
+        drop(guard);
+        holder.join().unwrap();
+    }
+
+    #[test]
+    fn bump_hands_over_to_waiter() {
+        let m = Arc::new(Mutex::new(Vec::new()));
+        let mut guard = m.lock();
+
+        let m2 = m.clone();
+        let waiter = thread_spawn(move || m2.lock().push("waiter"));
+        // Make sure the waiter is actually spinning before we bump
+        sleep(SLEEP_TIME);
+
+        while guard.is_empty() {
+            SpinMutexGuard::bump(&mut guard);
+        }
+        guard.push("holder");
+        drop(guard);
+
+        waiter.join().unwrap();
+        assert_eq!(vec!["waiter", "holder"], *m.lock());
+    }
+
+    static_mutex! {
+        static COUNTER: usize = 0;
+    }
+
+    #[test]
+    fn static_counter_from_many_threads() {
+        const THREADS: usize = 8;
+        const ITERATIONS: usize = 1000;
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                thread_spawn(|| {
+                    for _ in 0..ITERATIONS {
+                        *COUNTER.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(THREADS * ITERATIONS, *COUNTER.lock());
+    }
+
+    #[test]
+    fn default_and_from() {
+        let m: SpinMutex<Vec<i32>> = SpinMutex::default();
+        assert!(m.lock().is_empty());
+
+        let m: SpinMutex<_, Backoff> = 7.into();
+        assert_eq!(7, *m.lock());
+    }
+
+    fn hammer<R: RelaxStrategy + 'static>() {
+        const THREADS: usize = 4;
+        const ITERATIONS: usize = 1000;
+
+        let m = Arc::new(SpinMutex::<_, R>::with_relax(0));
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let m = m.clone();
+                thread_spawn(move || {
+                    for _ in 0..ITERATIONS {
+                        *m.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(THREADS * ITERATIONS, *m.lock());
+    }
+
+    #[test]
+    fn every_relax_strategy_excludes() {
+        hammer::<Spin>();
+        hammer::<Backoff>();
+        #[cfg(feature = "std")]
+        {
+            hammer::<Yield>();
+            hammer::<SpinThenYield>();
+        }
+    }
+
+    #[cfg(feature = "send_guard")]
+    #[test]
+    fn guard_can_be_released_on_another_thread() {
+        let m = Mutex::new(0);
+        let mut guard = m.lock();
+        std::thread::scope(|s| {
+            s.spawn(move || *guard += 1);
+        });
+        assert_eq!(1, *m.lock());
     }
 }
@@ -0,0 +1,240 @@
+// Narrowing a locked guard down to part of the data, same model as parking_lot: the mapped guard only keeps a
+// reference to the lock flag and to the part, and still releases the whole mutex when dropped.
+//
+// These are associated functions rather than methods (`SpinMutexGuard::map(guard, ...)`) so they can't shadow a
+// `map` method on `T` reached through `Deref`.
+
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::{Deref, DerefMut, Drop};
+use core::ptr;
+
+use crate::sync::Access;
+use crate::{release, GuardMarker, LockWord, SpinMutexGuard};
+
+pub struct MappedSpinMutexGuard<'a, T: ?Sized> {
+    lock: &'a LockWord,
+    data: &'a mut T,
+    // Still the access to the whole of the mutex's data
+    access: Access,
+    _marker: GuardMarker,
+}
+
+impl<'a, T: ?Sized> SpinMutexGuard<'a, T> {
+    pub fn map<U: ?Sized, F>(s: Self, f: F) -> MappedSpinMutexGuard<'a, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        let (lock, data, access) = s.into_parts();
+        MappedSpinMutexGuard {
+            lock,
+            data: f(data),
+            access,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Like `map`, but `f` can decline, in which case you get the original guard back, still locked.
+    pub fn filter_map<U: ?Sized, F>(s: Self, f: F) -> Result<MappedSpinMutexGuard<'a, U>, Self>
+    where
+        F: FnOnce(&mut T) -> Option<&mut U>,
+    {
+        Self::try_map(s, |data| f(data).ok_or(())).map_err(|(s, ())| s)
+    }
+
+    /// Like `filter_map`, but `f` gets to say why it declined.
+    pub fn try_map<U: ?Sized, E, F>(s: Self, f: F) -> Result<MappedSpinMutexGuard<'a, U>, (Self, E)>
+    where
+        F: FnOnce(&mut T) -> Result<&mut U, E>,
+    {
+        let relock = s.relock;
+        // The guard is gone before `f` runs, so the pointer is the only way to the data. If `f` declines, whatever it
+        // was handed is dead and the guard gets rebuilt from the pointer.
+        let (lock, data, access) = s.into_parts();
+        let data: *mut T = data;
+        match f(unsafe { &mut *data }) {
+            Ok(data) => Ok(MappedSpinMutexGuard {
+                lock,
+                data,
+                access,
+                _marker: PhantomData,
+            }),
+            Err(e) => Err((
+                SpinMutexGuard {
+                    lock,
+                    data: unsafe { &mut *data },
+                    access,
+                    relock,
+                    _marker: PhantomData,
+                },
+                e,
+            )),
+        }
+    }
+
+    // Takes the guard apart without running its `Drop`, the lock stays held
+    pub(crate) fn into_parts(self) -> (&'a LockWord, &'a mut T, Access) {
+        let mut s = mem::ManuallyDrop::new(self);
+        let data: *mut T = &mut *s.data;
+        (s.lock, unsafe { &mut *data }, unsafe {
+            ptr::read(&s.access)
+        })
+    }
+}
+
+impl<'a, T: ?Sized> MappedSpinMutexGuard<'a, T> {
+    pub fn map<U: ?Sized, F>(s: Self, f: F) -> MappedSpinMutexGuard<'a, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        let (lock, data, access) = s.into_parts();
+        MappedSpinMutexGuard {
+            lock,
+            data: f(data),
+            access,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn filter_map<U: ?Sized, F>(s: Self, f: F) -> Result<MappedSpinMutexGuard<'a, U>, Self>
+    where
+        F: FnOnce(&mut T) -> Option<&mut U>,
+    {
+        Self::try_map(s, |data| f(data).ok_or(())).map_err(|(s, ())| s)
+    }
+
+    // Same dance as `SpinMutexGuard::try_map`
+    pub fn try_map<U: ?Sized, E, F>(s: Self, f: F) -> Result<MappedSpinMutexGuard<'a, U>, (Self, E)>
+    where
+        F: FnOnce(&mut T) -> Result<&mut U, E>,
+    {
+        let (lock, data, access) = s.into_parts();
+        let data: *mut T = data;
+        match f(unsafe { &mut *data }) {
+            Ok(data) => Ok(MappedSpinMutexGuard {
+                lock,
+                data,
+                access,
+                _marker: PhantomData,
+            }),
+            Err(e) => Err((
+                Self {
+                    lock,
+                    data: unsafe { &mut *data },
+                    access,
+                    _marker: PhantomData,
+                },
+                e,
+            )),
+        }
+    }
+
+    fn into_parts(self) -> (&'a LockWord, &'a mut T, Access) {
+        let mut s = mem::ManuallyDrop::new(self);
+        let data: *mut T = &mut *s.data;
+        (s.lock, unsafe { &mut *data }, unsafe {
+            ptr::read(&s.access)
+        })
+    }
+}
+
+impl<'a, T: ?Sized> Drop for MappedSpinMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.access.end();
+        release(self.lock);
+    }
+}
+
+impl<'a, T: ?Sized> Deref for MappedSpinMutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for MappedSpinMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.data
+    }
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MappedSpinMutexGuard<'_, T> {}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use crate::SpinMutex;
+
+    use super::*;
+
+    struct Big {
+        name: &'static str,
+        values: Vec<i32>,
+    }
+
+    fn big() -> SpinMutex<Big> {
+        SpinMutex::new(Big {
+            name: "big",
+            values: vec![1, 2, 3],
+        })
+    }
+
+    fn bump_all(values: &mut [i32]) {
+        for v in values {
+            *v += 1;
+        }
+    }
+
+    #[test]
+    fn map_keeps_lock_until_dropped() {
+        let m = big();
+        let mut values = SpinMutexGuard::map(m.lock(), |b| &mut b.values);
+        bump_all(&mut values);
+        assert!(m.try_lock().is_none());
+
+        let first = MappedSpinMutexGuard::map(values, |v| &mut v[0]);
+        assert_eq!(2, *first);
+        drop(first);
+
+        assert_eq!(vec![2, 3, 4], m.lock().values);
+    }
+
+    #[test]
+    fn filter_map_gives_guard_back() {
+        let m = big();
+        let guard = SpinMutexGuard::filter_map(m.lock(), |b| b.values.get_mut(10))
+            .err()
+            .expect("there's no tenth value");
+        assert_eq!("big", guard.name);
+        assert!(m.try_lock().is_none());
+        drop(guard);
+
+        let mapped = SpinMutexGuard::filter_map(m.lock(), |b| b.values.last_mut())
+            .ok()
+            .unwrap();
+        assert_eq!(3, *mapped);
+        assert!(m.try_lock().is_none());
+        drop(mapped);
+        assert!(m.try_lock().is_some());
+    }
+
+    #[test]
+    fn try_map_reports_why() {
+        let m = big();
+        let Err((guard, why)) = SpinMutexGuard::try_map(m.lock(), |b| -> Result<&mut i32, _> {
+            Err(format!("{} has no such field", b.name))
+        }) else {
+            panic!("closure always fails");
+        };
+        assert_eq!("big has no such field", why);
+        assert!(m.try_lock().is_none());
+        drop(guard);
+
+        let mapped = SpinMutexGuard::try_map(m.lock(), |b| b.values.first_mut().ok_or(()))
+            .ok()
+            .unwrap();
+        let mapped = MappedSpinMutexGuard::try_map(mapped, |v| Ok::<_, ()>(v))
+            .ok()
+            .unwrap();
+        assert_eq!(1, *mapped);
+    }
+}
@@ -0,0 +1,363 @@
+// Mellor-Crummey & Scott queue lock. Waiters form a linked list through their `McsNode`s and each one spins on the
+// `locked` flag in its *own* node, so a release only ever touches the cache line of the single thread being woken,
+// instead of every waiter's copy of one shared flag.
+
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut, Drop};
+use core::ptr::{self, NonNull};
+#[cfg(feature = "std")]
+use std::cell::RefCell;
+
+use crate::relax::{RelaxStrategy, Spin};
+#[cfg(feature = "std")]
+use crate::sync::thread_local;
+use crate::sync::{const_fn, hint, Access, AtomicBool, AtomicPtr, Ordering, UnsafeCell};
+use crate::GuardMarker;
+
+/// One waiter's place in an `McsMutex` queue. Has to stay put until the lock it was queued for has been released,
+/// which `McsMutex::lock_with` enforces by only lending out the data for the length of a closure.
+///
+/// Without the `std` feature there's no thread-local pool to draw nodes from, so `lock_with` is the only way in.
+pub struct McsNode {
+    next: AtomicPtr<McsNode>,
+    locked: AtomicBool,
+}
+
+impl McsNode {
+    const_fn! {
+        pub fn new() -> Self {
+            Self {
+                next: AtomicPtr::new(ptr::null_mut()),
+                locked: AtomicBool::new(false),
+            }
+        }
+    }
+}
+
+impl Default for McsNode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// More than this many MCS locks held at once by one thread is unusual enough that we just free the extra nodes
+#[cfg(feature = "std")]
+const POOL_CAPACITY: usize = 8;
+
+#[cfg(feature = "std")]
+thread_local! {
+    // Boxed because a node has to stay at one address while it's out of the pool sitting in some queue.
+    // Not `const` because loom's `thread_local!` doesn't accept that syntax.
+    #[allow(clippy::vec_box, clippy::missing_const_for_thread_local)]
+    static NODE_POOL: RefCell<Vec<Box<McsNode>>> = RefCell::new(Vec::new());
+}
+
+#[cfg(feature = "std")]
+fn take_pooled_node() -> Box<McsNode> {
+    NODE_POOL
+        .try_with(|pool| pool.borrow_mut().pop())
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "std")]
+fn return_pooled_node(node: Box<McsNode>) {
+    // During thread teardown the pool may already be gone, in which case the node is simply freed
+    let _ = NODE_POOL.try_with(|pool| {
+        let mut pool = pool.borrow_mut();
+        if pool.len() < POOL_CAPACITY {
+            pool.push(node);
+        }
+    });
+}
+
+pub struct McsMutex<T, R = Spin> {
+    tail: AtomicPtr<McsNode>,
+    data: UnsafeCell<T>,
+    relax: PhantomData<R>,
+}
+
+impl<T> McsMutex<T> {
+    const_fn! {
+        pub fn new(data: T) -> Self {
+            Self::with_relax(data)
+        }
+    }
+}
+
+impl<T, R: RelaxStrategy> McsMutex<T, R> {
+    const_fn! {
+        pub fn with_relax(data: T) -> Self {
+            Self {
+                tail: AtomicPtr::new(ptr::null_mut()),
+                data: UnsafeCell::new(data),
+                relax: PhantomData,
+            }
+        }
+    }
+
+    /// Queues up using a node from this thread's pool (allocating one the first time).
+    #[cfg(feature = "std")]
+    pub fn lock(&self) -> McsMutexGuard<'_, T> {
+        let node = NonNull::from(Box::leak(take_pooled_node()));
+        unsafe { self.enqueue(node) };
+        McsMutexGuard::from(self, node, true)
+    }
+
+    /// Queues up using a node owned by the caller, e.g. one on the stack, so locking never allocates, and runs `f`
+    /// with the lock held.
+    ///
+    /// There's no guard to hand out here: one could be leaked with `mem::forget`, leaving `tail` pointing at a node
+    /// that's gone by the time the next thread links itself to it.
+    pub fn lock_with<U>(&self, node: &mut McsNode, f: impl FnOnce(&mut T) -> U) -> U {
+        let node = NonNull::from(node);
+        unsafe { self.enqueue(node) };
+        // Released when this goes out of scope, unwinding included, while `node` is still borrowed
+        let mut guard = McsMutexGuard::from(self, node, false);
+        f(&mut guard)
+    }
+
+    /// Only succeeds if the queue is completely empty.
+    #[cfg(feature = "std")]
+    pub fn try_lock(&self) -> Option<McsMutexGuard<'_, T>> {
+        let node = NonNull::from(Box::leak(take_pooled_node()));
+        if unsafe { self.try_enqueue(node) } {
+            Some(McsMutexGuard::from(self, node, true))
+        } else {
+            return_pooled_node(unsafe { Box::from_raw(node.as_ptr()) });
+            None
+        }
+    }
+
+    /// Runs `f` if the queue is completely empty, see `lock_with`.
+    pub fn try_lock_with<U>(&self, node: &mut McsNode, f: impl FnOnce(&mut T) -> U) -> Option<U> {
+        let node = NonNull::from(node);
+        if unsafe { self.try_enqueue(node) } {
+            let mut guard = McsMutexGuard::from(self, node, false);
+            Some(f(&mut guard))
+        } else {
+            None
+        }
+    }
+
+    // SAFETY: same as `enqueue`, if it returns true
+    unsafe fn try_enqueue(&self, node: NonNull<McsNode>) -> bool {
+        node.as_ref().next.store(ptr::null_mut(), Ordering::Relaxed);
+        self.tail
+            .compare_exchange(
+                ptr::null_mut(),
+                node.as_ptr(),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    // SAFETY: `node` must stay valid and unmoved until the guard built from it is dropped
+    unsafe fn enqueue(&self, node: NonNull<McsNode>) {
+        let node_ref = node.as_ref();
+        node_ref.next.store(ptr::null_mut(), Ordering::Relaxed);
+        node_ref.locked.store(true, Ordering::Relaxed);
+
+        // AcqRel: Release so whoever links to us sees `locked == true`, Acquire in case the queue was empty and we
+        // need to synchronize with the last holder's release of `tail`
+        let predecessor = self.tail.swap(node.as_ptr(), Ordering::AcqRel);
+        if predecessor.is_null() {
+            return;
+        }
+
+        (*predecessor).next.store(node.as_ptr(), Ordering::Release);
+        let mut relax = R::default();
+        // Acquire pairs with the predecessor's Release when it hands the lock to us
+        while node_ref.locked.load(Ordering::Acquire) {
+            relax.relax();
+        }
+    }
+}
+
+pub struct McsMutexGuard<'a, T> {
+    tail: &'a AtomicPtr<McsNode>,
+    node: NonNull<McsNode>,
+    pooled: bool,
+    data: &'a mut T,
+    access: Access,
+    _marker: GuardMarker,
+}
+
+impl<'a, T> McsMutexGuard<'a, T> {
+    pub(crate) fn from<R>(m: &'a McsMutex<T, R>, node: NonNull<McsNode>, pooled: bool) -> Self {
+        let (data, access) = unsafe { m.data.write() };
+        Self {
+            tail: &m.tail,
+            node,
+            pooled,
+            data,
+            access,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T> Drop for McsMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.access.end();
+        let node = unsafe { self.node.as_ref() };
+
+        let mut next = node.next.load(Ordering::Acquire);
+        if next.is_null() {
+            // Nobody visibly queued behind us, so try to mark the lock free
+            if self
+                .tail
+                .compare_exchange(
+                    self.node.as_ptr(),
+                    ptr::null_mut(),
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                self.recycle_node();
+                return;
+            }
+
+            // Someone swapped themselves into `tail` but hasn't linked into our `next` yet; it's only a few
+            // instructions away, so wait for it
+            loop {
+                next = node.next.load(Ordering::Acquire);
+                if !next.is_null() {
+                    break;
+                }
+                hint::spin_loop();
+            }
+        }
+
+        unsafe { (*next).locked.store(false, Ordering::Release) };
+        self.recycle_node();
+    }
+}
+
+impl<'a, T> McsMutexGuard<'a, T> {
+    fn recycle_node(&mut self) {
+        #[cfg(feature = "std")]
+        if self.pooled {
+            return_pooled_node(unsafe { Box::from_raw(self.node.as_ptr()) });
+        }
+        #[cfg(not(feature = "std"))]
+        debug_assert!(!self.pooled, "pooled nodes need the `std` feature");
+    }
+}
+
+impl<'a, T> Deref for McsMutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+impl<'a, T> DerefMut for McsMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.data
+    }
+}
+
+unsafe impl<T: Send, R> Send for McsMutex<T, R> {}
+unsafe impl<T: Send, R> Sync for McsMutex<T, R> {}
+
+// The node pointer is what keeps the guard from being auto `Send`; a pooled node is just a heap allocation, so
+// handing it back to a different thread's pool is fine
+#[cfg(feature = "send_guard")]
+unsafe impl<T: Send> Send for McsMutexGuard<'_, T> {}
+unsafe impl<T: Sync> Sync for McsMutexGuard<'_, T> {}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread::spawn as thread_spawn;
+
+    const THREADS: usize = 4;
+    const ITERATIONS: usize = 1000;
+
+    // Even threads bring their own node, odd ones use the pool when there is one
+    fn hammer(pooled: bool) {
+        let m = Arc::new(McsMutex::new(0));
+        let handles: Vec<_> = (0..THREADS)
+            .map(|i| {
+                let m = m.clone();
+                thread_spawn(move || {
+                    let mut node = McsNode::new();
+                    for _ in 0..ITERATIONS {
+                        if pooled && i % 2 == 1 {
+                            #[cfg(feature = "std")]
+                            {
+                                *m.lock() += 1;
+                            }
+                        } else {
+                            m.lock_with(&mut node, |n| *n += 1);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(
+            THREADS * ITERATIONS,
+            m.lock_with(&mut McsNode::new(), |n| *n)
+        );
+    }
+
+    #[test]
+    fn stack_nodes_exclude_each_other() {
+        hammer(false);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn stack_and_pooled_nodes_exclude_each_other() {
+        hammer(true);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn try_lock_only_when_queue_empty() {
+        let m = McsMutex::new(());
+        let guard = m.lock();
+        assert!(m.try_lock().is_none());
+        drop(guard);
+        assert!(m.try_lock().is_some());
+    }
+
+    #[test]
+    fn try_lock_with_only_when_queue_empty() {
+        let m = McsMutex::new(());
+        let mut node = McsNode::new();
+        let mut other = McsNode::new();
+        let nested = m.try_lock_with(&mut node, |_| m.try_lock_with(&mut other, |_| ()));
+        assert_eq!(Some(None), nested);
+        assert!(m.try_lock_with(&mut other, |_| ()).is_some());
+    }
+
+    #[test]
+    fn lock_with_releases_on_panic() {
+        let m = McsMutex::new(0);
+        let mut node = McsNode::new();
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            m.lock_with(&mut node, |_| panic!("oops"))
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(1, m.lock_with(&mut node, |n| *n + 1));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn nodes_are_reused() {
+        let m = McsMutex::new(());
+        let first = m.lock().node;
+        let second = m.lock().node;
+        assert_eq!(first, second);
+    }
+}
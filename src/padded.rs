@@ -0,0 +1,140 @@
+// Locks sitting next to each other in an array share cache lines, so threads hammering *different* locks still
+// steal the line back and forth from each other (false sharing). Padding each one out to a whole line fixes that, at
+// the cost of the memory.
+
+use core::ops::{Deref, DerefMut};
+
+use crate::relax::{RelaxStrategy, Spin};
+use crate::sync::const_fn;
+use crate::SpinMutex;
+
+/// Aligns (and so pads) `T` to a cache line.
+///
+/// 128 bytes on x86_64, aarch64 and powerpc64: Intel's spatial prefetcher pulls in lines in pairs, and the big ARM
+/// and POWER cores have 128-byte lines to begin with. 64 everywhere else.
+#[cfg_attr(
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "powerpc64"
+    ),
+    repr(align(128))
+)]
+#[cfg_attr(
+    not(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "powerpc64"
+    )),
+    repr(align(64))
+)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CachePadded<T> {
+    value: T,
+}
+
+impl<T> CachePadded<T> {
+    pub const fn new(value: T) -> Self {
+        Self { value }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for CachePadded<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> From<T> for CachePadded<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+/// A `SpinMutex` that gets a cache line to itself, for arrays of locks like per-bucket or per-shard ones. Derefs to
+/// the mutex, so it locks like any other.
+pub struct PaddedSpinMutex<T, R = Spin>(CachePadded<SpinMutex<T, R>>);
+
+impl<T> PaddedSpinMutex<T> {
+    const_fn! {
+        pub fn new(data: T) -> Self {
+            Self::with_relax(data)
+        }
+    }
+}
+
+impl<T, R: RelaxStrategy> PaddedSpinMutex<T, R> {
+    const_fn! {
+        pub fn with_relax(data: T) -> Self {
+            Self(CachePadded::new(SpinMutex::with_relax(data)))
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0.into_inner().into_inner()
+    }
+}
+
+impl<T, R> Deref for PaddedSpinMutex<T, R> {
+    type Target = SpinMutex<T, R>;
+    fn deref(&self) -> &SpinMutex<T, R> {
+        &self.0
+    }
+}
+
+impl<T, R> DerefMut for PaddedSpinMutex<T, R> {
+    fn deref_mut(&mut self) -> &mut SpinMutex<T, R> {
+        &mut self.0
+    }
+}
+
+impl<T: Default, R: RelaxStrategy> Default for PaddedSpinMutex<T, R> {
+    fn default() -> Self {
+        Self::with_relax(T::default())
+    }
+}
+
+impl<T, R: RelaxStrategy> From<T> for PaddedSpinMutex<T, R> {
+    fn from(data: T) -> Self {
+        Self::with_relax(data)
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+    use core::mem::{align_of, size_of};
+
+    #[test]
+    fn neighbours_never_share_a_line() {
+        let line = align_of::<CachePadded<u8>>();
+        assert!(line >= 64);
+        assert_eq!(line, size_of::<PaddedSpinMutex<u64>>());
+
+        let locks: [PaddedSpinMutex<u64>; 4] = Default::default();
+        for pair in locks.windows(2) {
+            let a = &*pair[0] as *const SpinMutex<u64> as usize;
+            let b = &*pair[1] as *const SpinMutex<u64> as usize;
+            assert_eq!(0, a % line);
+            assert_eq!(line, b - a);
+        }
+    }
+
+    #[test]
+    fn derefs_to_the_mutex() {
+        let m = PaddedSpinMutex::new(1);
+        *m.lock() += 1;
+        assert_eq!(2, m.into_inner());
+    }
+}
@@ -0,0 +1,161 @@
+// A `SpinMutex` that remembers if a thread panicked while holding it, the way `std::sync::Mutex` does. Kept as its own
+// type so plain `SpinMutex` users don't pay for the extra flag or have to unwrap every `lock()`.
+//
+// The result and error types are std's own, so code matching on a `std::sync::Mutex` result works unchanged.
+
+use std::ops::{Deref, DerefMut, Drop};
+use std::thread;
+
+pub use std::sync::{LockResult, PoisonError, TryLockError, TryLockResult};
+
+use crate::relax::{RelaxStrategy, Spin};
+use crate::sync::{const_fn, AtomicBool, Ordering};
+use crate::{SpinMutex, SpinMutexGuard};
+
+pub struct PoisonSpinMutex<T, R = Spin> {
+    inner: SpinMutex<T, R>,
+    poisoned: AtomicBool,
+}
+
+impl<T> PoisonSpinMutex<T> {
+    const_fn! {
+        pub fn new(data: T) -> Self {
+            Self::with_relax(data)
+        }
+    }
+}
+
+impl<'a, T, R: RelaxStrategy> PoisonSpinMutex<T, R> {
+    const_fn! {
+        pub fn with_relax(data: T) -> Self {
+            Self {
+                inner: SpinMutex::with_relax(data),
+                poisoned: AtomicBool::new(false),
+            }
+        }
+    }
+
+    /// Errors if a previous holder panicked, but the error still carries the guard, so the lock is held either way.
+    pub fn lock(&'a self) -> LockResult<PoisonSpinMutexGuard<'a, T>> {
+        self.guard(self.inner.lock())
+    }
+
+    pub fn try_lock(&'a self) -> TryLockResult<PoisonSpinMutexGuard<'a, T>> {
+        match self.inner.try_lock() {
+            Some(guard) => Ok(self.guard(guard)?),
+            None => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// For when whoever handles the `PoisonError` has put the data back into a usable state.
+    pub fn clear_poison(&self) {
+        self.poisoned.store(false, Ordering::Relaxed);
+    }
+
+    fn guard(&'a self, inner: SpinMutexGuard<'a, T>) -> LockResult<PoisonSpinMutexGuard<'a, T>> {
+        let guard = PoisonSpinMutexGuard {
+            inner,
+            poisoned: &self.poisoned,
+            panicking: thread::panicking(),
+        };
+        if self.is_poisoned() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+}
+
+pub struct PoisonSpinMutexGuard<'a, T> {
+    // Fields are only dropped after our own `drop` has run, so the lock is still held while that sets the flag,
+    // whatever order they are declared in
+    inner: SpinMutexGuard<'a, T>,
+    poisoned: &'a AtomicBool,
+    // Locking from a destructor while already unwinding isn't the critical section that panicked, so that alone
+    // shouldn't poison anything
+    panicking: bool,
+}
+
+impl<'a, T> Drop for PoisonSpinMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        if !self.panicking && thread::panicking() {
+            // Relaxed is enough, `inner` is released right after this with Release ordering
+            self.poisoned.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<'a, T> Deref for PoisonSpinMutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<'a, T> DerefMut for PoisonSpinMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread::spawn as thread_spawn;
+
+    fn poison<T: Send + 'static>(m: &Arc<PoisonSpinMutex<T>>) {
+        let m = m.clone();
+        let result = thread_spawn(move || {
+            let _guard = m.lock().unwrap();
+            panic!("poisoning on purpose");
+        })
+        .join();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn starts_unpoisoned() {
+        let m = PoisonSpinMutex::new(0);
+        *m.lock().unwrap() += 1;
+        assert!(!m.is_poisoned());
+        assert_eq!(1, *m.try_lock().unwrap());
+    }
+
+    #[test]
+    fn panic_while_locked_poisons() {
+        let m = Arc::new(PoisonSpinMutex::new(0));
+        poison(&m);
+        assert!(m.is_poisoned());
+
+        // The lock is still usable through the error
+        let Err(err) = m.lock() else {
+            panic!("lock should report the poisoning");
+        };
+        let mut guard = err.into_inner();
+        *guard = 1;
+        drop(guard);
+
+        assert!(matches!(m.try_lock(), Err(TryLockError::Poisoned(_))));
+    }
+
+    #[test]
+    fn clear_poison_recovers() {
+        let m = Arc::new(PoisonSpinMutex::new(0));
+        poison(&m);
+        m.clear_poison();
+        assert!(!m.is_poisoned());
+        assert!(m.lock().is_ok());
+    }
+
+    #[test]
+    fn try_lock_would_block() {
+        let m = PoisonSpinMutex::new(0);
+        let _guard = m.lock().unwrap();
+        assert!(matches!(m.try_lock(), Err(TryLockError::WouldBlock)));
+    }
+}
@@ -0,0 +1,402 @@
+// The lock itself, without any data attached. `SpinMutex` is just this plus an `UnsafeCell`, and it's usable on its own
+// for guarding memory Rust doesn't own, like a buffer shared over FFI. With the `lock_api` feature it's also a
+// `lock_api::RawMutex`, so `lock_api::Mutex<RawSpinLock, T>` behaves just like `SpinMutex<T>`.
+
+use core::marker::PhantomData;
+use core::ops::{Deref, Drop};
+#[cfg(all(feature = "lock_api", feature = "std", not(feature = "loom")))]
+use std::time::Duration;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+use crate::relax::{RelaxStrategy, Spin};
+use crate::sync::{const_fn, hint, AtomicU8, Ordering};
+use crate::GuardMarker;
+
+pub struct RawSpinLock<R = Spin> {
+    pub(crate) word: LockWord,
+    relax: PhantomData<R>,
+}
+
+impl RawSpinLock {
+    const_fn! {
+        pub fn new() -> Self {
+            Self::with_relax()
+        }
+    }
+}
+
+impl<R: RelaxStrategy> RawSpinLock<R> {
+    const_fn! {
+        /// Like `new`, but waiters relax using `R`, e.g. `RawSpinLock::<Backoff>::with_relax()`.
+        pub fn with_relax() -> Self {
+            Self {
+                word: LockWord::new(),
+                relax: PhantomData,
+            }
+        }
+    }
+
+    pub fn lock(&self) -> RawSpinLockGuard<'_> {
+        acquire::<R>(&self.word);
+        RawSpinLockGuard::from(&self.word)
+    }
+
+    pub fn try_lock(&self) -> Option<RawSpinLockGuard<'_>> {
+        try_acquire(&self.word).then(|| RawSpinLockGuard::from(&self.word))
+    }
+}
+
+impl<R> RawSpinLock<R> {
+    /// Releases a lock whose guard was `mem::forget`ten, e.g. one locked on the Rust side of an FFI boundary and
+    /// unlocked from a callback on the other.
+    ///
+    /// # Safety
+    ///
+    /// The lock has to be held, and nothing may still be relying on it, in particular no live guard for it.
+    pub unsafe fn unlock(&self) {
+        release(&self.word);
+    }
+
+    /// Only a snapshot, the answer can be stale before you get to act on it.
+    pub fn is_locked(&self) -> bool {
+        self.word.load(Ordering::Relaxed) & LOCKED != 0
+    }
+}
+
+impl<R: RelaxStrategy> Default for RawSpinLock<R> {
+    fn default() -> Self {
+        Self::with_relax()
+    }
+}
+
+#[must_use = "the lock is released as soon as the guard is dropped"]
+pub struct RawSpinLockGuard<'a> {
+    lock: &'a LockWord,
+    _marker: GuardMarker,
+}
+
+impl<'a> RawSpinLockGuard<'a> {
+    fn from(lock: &'a LockWord) -> Self {
+        Self {
+            lock,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl Drop for RawSpinLockGuard<'_> {
+    fn drop(&mut self) {
+        release(self.lock);
+    }
+}
+
+// There's no data behind it to race on
+unsafe impl Sync for RawSpinLockGuard<'_> {}
+
+// The lock word, plus whatever the optional features keep per mutex and need to update on every lock and unlock.
+// Derefs to the flag itself, which is all most of the crate cares about.
+pub(crate) struct LockWord {
+    flag: AtomicU8,
+    #[cfg(feature = "stats")]
+    pub(crate) stats: crate::stats::Stats,
+    // Only ever handed out to `SpinMutex`es, so every other lock skips the held-list lookup on release
+    #[cfg(feature = "deadlock-detection")]
+    pub(crate) id: crate::deadlock::LockId,
+    #[cfg(feature = "watchdog")]
+    pub(crate) owner: crate::watchdog::Owner,
+}
+
+impl LockWord {
+    const_fn! {
+        pub(crate) fn new() -> Self {
+            Self {
+                flag: AtomicU8::new(0),
+                #[cfg(feature = "stats")]
+                stats: crate::stats::Stats::new(),
+                #[cfg(feature = "deadlock-detection")]
+                id: crate::deadlock::LockId::new(),
+                #[cfg(feature = "watchdog")]
+                owner: crate::watchdog::Owner::new(),
+            }
+        }
+    }
+}
+
+impl Deref for LockWord {
+    type Target = AtomicU8;
+    fn deref(&self) -> &AtomicU8 {
+        &self.flag
+    }
+}
+
+// The flag handling lives in free functions so guards can take the lock again too, without knowing the lock's `R`.
+//
+// With the `async` feature, besides the lock bit the word has a bit saying some `lock_async` future is parked on it. Keeping both in one word
+// means the unlocking swap learns whether anyone needs waking in the same atomic step, so a future can never register
+// just after we looked and miss its wakeup.
+pub(crate) const LOCKED: u8 = 1;
+#[cfg(feature = "async")]
+pub(crate) const WAITERS: u8 = 1 << 1;
+
+pub(crate) fn try_acquire(lock: &LockWord) -> bool {
+    // Acquire on success pairs with the Release in `release`, so everything the previous holder wrote is visible to
+    // us. A failed attempt doesn't get to touch the data, so it needs no ordering at all.
+    let acquired = lock.fetch_or(LOCKED, Ordering::Acquire) & LOCKED == 0;
+    if acquired {
+        #[cfg(feature = "watchdog")]
+        crate::watchdog::acquired(lock);
+        #[cfg(feature = "stats")]
+        lock.stats.acquired();
+    }
+    acquired
+}
+
+pub(crate) fn acquire<R: RelaxStrategy>(lock: &LockWord) {
+    let mut relax = R::default();
+    #[cfg(feature = "watchdog")]
+    let mut watch = crate::watchdog::Watch::new(lock);
+    #[cfg(feature = "stats")]
+    let mut wait = crate::stats::Wait::default();
+    while !try_acquire(lock) {
+        #[cfg(feature = "stats")]
+        wait.start();
+        // Test-and-test-and-set: only plain loads while the lock is held, which lets every waiter keep a shared copy
+        // of the cache line instead of each RMW stealing it exclusively from everyone else
+        while lock.load(Ordering::Relaxed) & LOCKED != 0 {
+            relax.relax();
+            #[cfg(feature = "watchdog")]
+            watch.spun();
+            #[cfg(feature = "stats")]
+            wait.spun();
+        }
+    }
+    #[cfg(feature = "stats")]
+    wait.finish(&lock.stats);
+}
+
+// Shared by `SpinMutex::try_lock_until` and `RawMutexTimed`. Tries once before looking at the clock, so a deadline
+// that's already passed still gets one attempt.
+#[cfg(feature = "std")]
+pub(crate) fn try_acquire_until<R: RelaxStrategy>(lock: &LockWord, deadline: Instant) -> bool {
+    let mut relax = R::default();
+    loop {
+        if try_acquire(lock) {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        relax.relax();
+    }
+}
+
+pub(crate) fn release(lock: &LockWord) {
+    #[cfg(feature = "deadlock-detection")]
+    crate::deadlock::released(lock);
+    // Before the unlock, once the lock is free somebody else's entry may already be on its way in
+    #[cfg(feature = "watchdog")]
+    crate::watchdog::released(lock);
+    #[cfg(feature = "stats")]
+    lock.stats.released();
+    // Release publishes our writes to whoever Acquires the lock next. Acquire makes the wakers a parked future
+    // registered before setting WAITERS visible to us.
+    #[cfg(feature = "async")]
+    if lock.swap(0, Ordering::AcqRel) & WAITERS != 0 {
+        crate::future::wake_all(lock);
+    }
+    // Nothing can be parked without `lock_async`, so a plain store does. Except under loom, which only orders a store
+    // after what the storing thread has seen, not after a waiter's failed `fetch_or`, so that waiter would never see
+    // the lock free. An RMW always reads the latest value.
+    #[cfg(not(any(feature = "async", feature = "loom")))]
+    lock.store(0, Ordering::Release);
+    #[cfg(all(feature = "loom", not(feature = "async")))]
+    lock.swap(0, Ordering::Release);
+}
+
+// The waiting part of `SpinMutexGuard::bump`, for after the lock has been released
+pub(crate) fn hang_back(lock: &LockWord) {
+    const HANG_BACK_SPINS: usize = 64;
+
+    for _ in 0..HANG_BACK_SPINS {
+        if lock.load(Ordering::Relaxed) & LOCKED != 0 {
+            break;
+        }
+        hint::spin_loop();
+    }
+}
+
+// `INIT` has to be a constant, which loom's atomics can't be built in
+#[cfg(all(feature = "lock_api", not(feature = "loom")))]
+unsafe impl<R: RelaxStrategy> lock_api::RawMutex for RawSpinLock<R> {
+    const INIT: Self = Self {
+        word: LockWord::new(),
+        relax: PhantomData,
+    };
+
+    #[cfg(not(feature = "send_guard"))]
+    type GuardMarker = lock_api::GuardNoSend;
+    #[cfg(feature = "send_guard")]
+    type GuardMarker = lock_api::GuardSend;
+
+    fn lock(&self) {
+        acquire::<R>(&self.word);
+    }
+
+    fn try_lock(&self) -> bool {
+        try_acquire(&self.word)
+    }
+
+    unsafe fn unlock(&self) {
+        release(&self.word);
+    }
+
+    fn is_locked(&self) -> bool {
+        RawSpinLock::is_locked(self)
+    }
+}
+
+// No `RawMutexFair`: there's no queue to hand the lock to, so a "fair" unlock could only hang back and hope a waiter
+// gets in first, which is no promise at all. `SpinMutexGuard::bump` does that much, and says so.
+
+#[cfg(all(feature = "lock_api", feature = "std", not(feature = "loom")))]
+unsafe impl<R: RelaxStrategy> lock_api::RawMutexTimed for RawSpinLock<R> {
+    type Duration = Duration;
+    type Instant = Instant;
+
+    fn try_lock_for(&self, timeout: Duration) -> bool {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.try_lock_until(deadline),
+            None => {
+                acquire::<R>(&self.word);
+                true
+            }
+        }
+    }
+
+    fn try_lock_until(&self, deadline: Instant) -> bool {
+        try_acquire_until::<R>(&self.word, deadline)
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+    use crate::Backoff;
+    use core::cell::UnsafeCell;
+    use core::mem;
+    use std::sync::Arc;
+    use std::thread::spawn as thread_spawn;
+
+    // Memory that isn't ours, guarded only by the lock, the way an FFI buffer would be
+    struct Region {
+        lock: RawSpinLock<Backoff>,
+        bytes: UnsafeCell<[u8; 64]>,
+    }
+    unsafe impl Sync for Region {}
+
+    #[test]
+    fn guards_a_region_it_does_not_own() {
+        const THREADS: usize = 4;
+        const ITERATIONS: usize = 1_000;
+
+        let region = Arc::new(Region {
+            lock: RawSpinLock::with_relax(),
+            bytes: UnsafeCell::new([0; 64]),
+        });
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let region = region.clone();
+                thread_spawn(move || {
+                    for _ in 0..ITERATIONS {
+                        let _guard = region.lock.lock();
+                        // Only a whole-buffer update that's never torn keeps every byte equal
+                        let bytes = unsafe { &mut *region.bytes.get() };
+                        let next = bytes[0].wrapping_add(1);
+                        bytes.fill(next);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let bytes = unsafe { &*region.bytes.get() };
+        assert!(bytes.iter().all(|&b| b == (THREADS * ITERATIONS) as u8));
+    }
+
+    #[test]
+    fn try_lock_and_manual_unlock() {
+        let lock = RawSpinLock::new();
+        let guard = lock.try_lock().unwrap();
+        assert!(lock.is_locked());
+        assert!(lock.try_lock().is_none());
+        drop(guard);
+        assert!(!lock.is_locked());
+
+        // Locked on "one side", unlocked on the other without the guard
+        mem::forget(lock.lock());
+        assert!(lock.is_locked());
+        unsafe { lock.unlock() };
+        assert!(lock.try_lock().is_some());
+    }
+}
+
+#[cfg(all(test, feature = "lock_api", not(feature = "loom")))]
+mod lock_api_tests {
+    use super::*;
+    use crate::{Backoff, SpinMutex};
+    use lock_api::{Mutex, RawMutex};
+    use std::sync::Arc;
+    use std::thread::spawn as thread_spawn;
+
+    // Written once against `lock_api`, the way code that's generic over the raw lock would be
+    fn hammer<L: RawMutex + Send + Sync + 'static>() -> usize {
+        const THREADS: usize = 4;
+        const ITERATIONS: usize = 10_000;
+
+        let m = Arc::new(Mutex::<L, usize>::new(0));
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let m = m.clone();
+                thread_spawn(move || {
+                    for _ in 0..ITERATIONS {
+                        *m.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let total = *m.lock();
+        assert_eq!(THREADS * ITERATIONS, total);
+        total
+    }
+
+    #[test]
+    fn works_as_a_lock_api_mutex() {
+        hammer::<RawSpinLock>();
+        hammer::<RawSpinLock<Backoff>>();
+
+        // Same answer as our own mutex
+        let ours = SpinMutex::new(0);
+        *ours.lock() += 1;
+        let theirs = Mutex::<RawSpinLock, _>::new(0);
+        *theirs.lock() += 1;
+        assert_eq!(*ours.lock(), *theirs.lock());
+    }
+
+    #[test]
+    fn try_lock_and_is_locked() {
+        let m = Mutex::<RawSpinLock, _>::new(0);
+        let guard = m.lock();
+        assert!(m.is_locked());
+        assert!(m.try_lock().is_none());
+        assert!(m.try_lock_for(Duration::from_millis(10)).is_none());
+        drop(guard);
+        assert!(!m.is_locked());
+        assert!(m.try_lock_for(Duration::from_millis(10)).is_some());
+    }
+}
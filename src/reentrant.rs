@@ -0,0 +1,207 @@
+// A spin lock the thread holding it can lock again. The lock word is the owner's thread id, plus a recursion count only
+// the owner ever touches. Like std's `ReentrantLock` the guards only hand out `&T`, since two nested guards for the same
+// data are alive at once and `&mut` from both would alias.
+
+use crate::relax::{RelaxStrategy, Spin};
+use crate::sync::{const_fn, current_thread_id, Access, AtomicUsize, Ordering, UnsafeCell};
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::ops::{Deref, Drop};
+
+pub struct ReentrantSpinMutex<T, R = Spin> {
+    owner: AtomicUsize,
+    count: Cell<usize>,
+    data: UnsafeCell<T>,
+    relax: PhantomData<R>,
+}
+
+impl<T> ReentrantSpinMutex<T> {
+    const_fn! {
+        pub fn new(data: T) -> Self {
+            Self::with_relax(data)
+        }
+    }
+}
+
+impl<'a, T, R: RelaxStrategy> ReentrantSpinMutex<T, R> {
+    const_fn! {
+        pub fn with_relax(data: T) -> Self {
+            Self {
+                owner: AtomicUsize::new(0),
+                count: Cell::new(0),
+                data: UnsafeCell::new(data),
+                relax: PhantomData,
+            }
+        }
+    }
+
+    /// Locks the mutex, or just bumps the recursion count if this thread already holds it.
+    pub fn lock(&'a self) -> ReentrantSpinMutexGuard<'a, T, R> {
+        let me = current_thread_id();
+        if !self.reenter(me) {
+            let mut relax = R::default();
+            while self
+                .owner
+                .compare_exchange_weak(0, me, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                while self.owner.load(Ordering::Relaxed) != 0 {
+                    relax.relax();
+                }
+            }
+            self.count.set(1);
+        }
+        ReentrantSpinMutexGuard::from(self)
+    }
+
+    pub fn try_lock(&'a self) -> Option<ReentrantSpinMutexGuard<'a, T, R>> {
+        let me = current_thread_id();
+        if !self.reenter(me) {
+            self.owner
+                .compare_exchange(0, me, Ordering::Acquire, Ordering::Relaxed)
+                .ok()?;
+            self.count.set(1);
+        }
+        Some(ReentrantSpinMutexGuard::from(self))
+    }
+
+    fn reenter(&self, me: usize) -> bool {
+        // Relaxed is enough: the only thread that could have stored our own id is us, so seeing it can't be stale
+        if self.owner.load(Ordering::Relaxed) != me {
+            return false;
+        }
+        let count = self.count.get().checked_add(1);
+        self.count
+            .set(count.expect("lock count overflow in reentrant mutex"));
+        true
+    }
+}
+
+pub struct ReentrantSpinMutexGuard<'a, T, R = Spin> {
+    lock: &'a ReentrantSpinMutex<T, R>,
+    data: &'a T,
+    access: Access,
+    // Always `!Send`, even with `send_guard`: unlocking on another thread would leave the owner id pointing at us
+    _marker: PhantomData<*const ()>,
+}
+
+impl<'a, T, R> ReentrantSpinMutexGuard<'a, T, R> {
+    pub(crate) fn from(m: &'a ReentrantSpinMutex<T, R>) -> Self {
+        // Shared, so loom is fine with the nested guards reading at once
+        let (data, access) = unsafe { m.data.read() };
+        Self {
+            lock: m,
+            data,
+            access,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T, R> Drop for ReentrantSpinMutexGuard<'a, T, R> {
+    fn drop(&mut self) {
+        self.access.end();
+        let count = self.lock.count.get() - 1;
+        self.lock.count.set(count);
+        if count == 0 {
+            self.lock.owner.store(0, Ordering::Release);
+        }
+    }
+}
+
+impl<'a, T, R> Deref for ReentrantSpinMutexGuard<'a, T, R> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+// `count` is a plain `Cell`, but only the owning thread touches it, and ownership moves between threads through the
+// Acquire/Release on `owner`. Only one thread at a time ever sees `&T`, so `T: Send` is enough, same as std.
+unsafe impl<T: Send, R> Send for ReentrantSpinMutex<T, R> {}
+unsafe impl<T: Send, R> Sync for ReentrantSpinMutex<T, R> {}
+
+unsafe impl<T: Sync, R> Sync for ReentrantSpinMutexGuard<'_, T, R> {}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::sync::Arc;
+    use std::thread::spawn as thread_spawn;
+
+    #[test]
+    fn same_thread_can_relock() {
+        let m = ReentrantSpinMutex::new(RefCell::new(Vec::new()));
+        let outer = m.lock();
+        outer.borrow_mut().push(1);
+        {
+            let inner = m.lock();
+            inner.borrow_mut().push(2);
+            let innermost = m.try_lock().expect("owner can always re-enter");
+            innermost.borrow_mut().push(3);
+            assert_eq!(3, m.count.get());
+        }
+        assert_eq!(1, m.count.get());
+        drop(outer);
+
+        assert_eq!(0, m.owner.load(Ordering::Relaxed));
+        assert_eq!(vec![1, 2, 3], *m.lock().borrow());
+    }
+
+    #[test]
+    fn other_threads_wait_for_the_outermost_guard() {
+        let m = Arc::new(ReentrantSpinMutex::new(()));
+        let outer = m.lock();
+        let inner = m.lock();
+
+        let m2 = m.clone();
+        assert!(thread_spawn(move || m2.try_lock().is_none())
+            .join()
+            .unwrap());
+
+        // Still held through `outer`
+        drop(inner);
+        let m2 = m.clone();
+        assert!(thread_spawn(move || m2.try_lock().is_none())
+            .join()
+            .unwrap());
+
+        drop(outer);
+        let m2 = m.clone();
+        assert!(thread_spawn(move || m2.try_lock().is_some())
+            .join()
+            .unwrap());
+    }
+
+    #[test]
+    fn nested_callbacks_from_many_threads() {
+        const THREADS: usize = 4;
+        const ITERATIONS: usize = 1_000;
+
+        fn bump(m: &ReentrantSpinMutex<RefCell<usize>>, depth: usize) {
+            let guard = m.lock();
+            *guard.borrow_mut() += 1;
+            if depth > 0 {
+                bump(m, depth - 1);
+            }
+        }
+
+        let m = Arc::new(ReentrantSpinMutex::new(RefCell::new(0)));
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let m = m.clone();
+                thread_spawn(move || {
+                    for _ in 0..ITERATIONS {
+                        bump(&m, 2);
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(THREADS * ITERATIONS * 3, *m.lock().borrow());
+    }
+}
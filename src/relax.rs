@@ -0,0 +1,98 @@
+// What a waiter does between peeks at a lock it couldn't get. A fresh strategy is made for every acquisition, so
+// stateful ones like `Backoff` start over each time.
+
+use crate::sync::hint;
+#[cfg(feature = "std")]
+use crate::sync::thread;
+
+pub trait RelaxStrategy: Default {
+    fn relax(&mut self);
+}
+
+/// Just tells the CPU we're spinning. Lowest latency hand-off, highest power draw.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct Spin;
+
+impl RelaxStrategy for Spin {
+    #[inline(always)]
+    fn relax(&mut self) {
+        hint::spin_loop();
+    }
+}
+
+/// Gives the rest of our timeslice to the OS scheduler every time.
+#[cfg(feature = "std")]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct Yield;
+
+#[cfg(feature = "std")]
+impl RelaxStrategy for Yield {
+    #[inline(always)]
+    fn relax(&mut self) {
+        thread::yield_now();
+    }
+}
+
+// 2^6 = 64 spins is about where spinning longer stops paying off (same cap crossbeam uses)
+const SPIN_LIMIT: u32 = 6;
+
+/// Spins twice as long as last time, up to `2^SPIN_LIMIT` spins per call.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct Backoff {
+    step: u32,
+}
+
+impl RelaxStrategy for Backoff {
+    #[inline]
+    fn relax(&mut self) {
+        for _ in 0..1 << self.step {
+            hint::spin_loop();
+        }
+        if self.step < SPIN_LIMIT {
+            self.step += 1;
+        }
+    }
+}
+
+/// Backs off like `Backoff` until hitting the cap, then starts yielding instead.
+#[cfg(feature = "std")]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct SpinThenYield {
+    backoff: Backoff,
+}
+
+#[cfg(feature = "std")]
+impl RelaxStrategy for SpinThenYield {
+    #[inline]
+    fn relax(&mut self) {
+        if self.backoff.step < SPIN_LIMIT {
+            self.backoff.relax();
+        } else {
+            thread::yield_now();
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_step_is_capped() {
+        let mut backoff = Backoff::default();
+        for _ in 0..100 {
+            backoff.relax();
+        }
+        assert_eq!(SPIN_LIMIT, backoff.step);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn spin_then_yield_stops_growing_at_cap() {
+        let mut relax = SpinThenYield::default();
+        for _ in 0..100 {
+            relax.relax();
+        }
+        assert_eq!(SPIN_LIMIT, relax.backoff.step);
+    }
+}
@@ -0,0 +1,441 @@
+// The whole lock state lives in one word: the low bit says a writer holds it, the next bit says an upgradeable reader
+// holds it, and everything above that counts plain readers.
+//
+// Nothing here stops a steady stream of readers from starving writers (or an upgrade) forever, same as most spin
+// rwlocks. If that matters for your workload, a `SpinMutex` is probably the better fit anyway.
+
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::{Deref, DerefMut, Drop};
+use core::ptr;
+
+use crate::relax::{RelaxStrategy, Spin};
+use crate::sync::{const_fn, Access, AtomicUsize, Ordering, UnsafeCell};
+use crate::GuardMarker;
+
+const WRITER: usize = 1;
+const UPGRADEABLE: usize = 1 << 1;
+const READER: usize = 1 << 2;
+
+pub struct SpinRwLock<T, R = Spin> {
+    state: AtomicUsize,
+    data: UnsafeCell<T>,
+    relax: PhantomData<R>,
+}
+
+impl<T> SpinRwLock<T> {
+    const_fn! {
+        pub fn new(data: T) -> Self {
+            Self::with_relax(data)
+        }
+    }
+}
+
+impl<'a, T, R: RelaxStrategy> SpinRwLock<T, R> {
+    const_fn! {
+        pub fn with_relax(data: T) -> Self {
+            Self {
+                state: AtomicUsize::new(0),
+                data: UnsafeCell::new(data),
+                relax: PhantomData,
+            }
+        }
+    }
+
+    pub fn read(&'a self) -> SpinRwLockReadGuard<'a, T> {
+        let mut relax = R::default();
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            while self.state.load(Ordering::Relaxed) & WRITER != 0 {
+                relax.relax();
+            }
+        }
+    }
+
+    /// Fails only if a writer holds the lock. An upgradeable reader doesn't keep other readers out.
+    pub fn try_read(&'a self) -> Option<SpinRwLockReadGuard<'a, T>> {
+        // Optimistically count ourselves in and back out if a writer beat us to it. Cheaper than a CAS loop when
+        // lots of readers arrive at once.
+        let state = self.state.fetch_add(READER, Ordering::Acquire);
+        if state & WRITER != 0 {
+            self.state.fetch_sub(READER, Ordering::Relaxed);
+            None
+        } else {
+            Some(SpinRwLockReadGuard::from(self))
+        }
+    }
+
+    pub fn write(&'a self) -> SpinRwLockWriteGuard<'a, T, R> {
+        let mut relax = R::default();
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+            while self.state.load(Ordering::Relaxed) != 0 {
+                relax.relax();
+            }
+        }
+    }
+
+    pub fn try_write(&'a self) -> Option<SpinRwLockWriteGuard<'a, T, R>> {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| SpinRwLockWriteGuard::from(self))
+    }
+
+    /// Reads alongside plain readers, but only one upgradeable reader at a time, so it can later `upgrade` without
+    /// racing another upgrade.
+    pub fn upgradeable_read(&'a self) -> SpinRwLockUpgradeableGuard<'a, T, R> {
+        let mut relax = R::default();
+        loop {
+            if let Some(guard) = self.try_upgradeable_read() {
+                return guard;
+            }
+            while self.state.load(Ordering::Relaxed) & (WRITER | UPGRADEABLE) != 0 {
+                relax.relax();
+            }
+        }
+    }
+
+    pub fn try_upgradeable_read(&'a self) -> Option<SpinRwLockUpgradeableGuard<'a, T, R>> {
+        // If a writer is in, this leaves a stray UPGRADEABLE bit behind. That's harmless, the bit only ever keeps out
+        // writers and other upgradeable readers, both already kept out by the writer, and the writer clears it on
+        // release.
+        if self.state.fetch_or(UPGRADEABLE, Ordering::Acquire) & (WRITER | UPGRADEABLE) == 0 {
+            Some(SpinRwLockUpgradeableGuard::from(self))
+        } else {
+            None
+        }
+    }
+}
+
+pub struct SpinRwLockReadGuard<'a, T> {
+    state: &'a AtomicUsize,
+    data: &'a T,
+    access: Access,
+    _marker: GuardMarker,
+}
+
+impl<'a, T> SpinRwLockReadGuard<'a, T> {
+    pub(crate) fn from<R>(l: &'a SpinRwLock<T, R>) -> Self {
+        let (data, access) = unsafe { l.data.read() };
+        Self {
+            state: &l.state,
+            data,
+            access,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T> Drop for SpinRwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.access.end();
+        self.state.fetch_sub(READER, Ordering::Release);
+    }
+}
+
+impl<'a, T> Deref for SpinRwLockReadGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+pub struct SpinRwLockWriteGuard<'a, T, R = Spin> {
+    lock: &'a SpinRwLock<T, R>,
+    data: &'a mut T,
+    access: Access,
+    _marker: GuardMarker,
+}
+
+impl<'a, T, R> SpinRwLockWriteGuard<'a, T, R> {
+    pub(crate) fn from(l: &'a SpinRwLock<T, R>) -> Self {
+        let (data, access) = unsafe { l.data.write() };
+        Self {
+            lock: l,
+            data,
+            access,
+            _marker: PhantomData,
+        }
+    }
+
+    // Gives up the guard without touching the lock state, for turning it into a different kind of guard. Has to
+    // happen before anyone else is let in.
+    fn into_lock(self) -> &'a SpinRwLock<T, R> {
+        let mut guard = mem::ManuallyDrop::new(self);
+        unsafe { ptr::drop_in_place(&mut guard.access) };
+        guard.lock
+    }
+}
+
+// Associated functions for the same reason as `SpinMutexGuard::map`, so they can't shadow a method on `T`
+impl<'a, T, R: RelaxStrategy> SpinRwLockWriteGuard<'a, T, R> {
+    /// Lets other readers back in without ever releasing the lock in between.
+    pub fn downgrade(s: Self) -> SpinRwLockReadGuard<'a, T> {
+        let lock = s.into_lock();
+
+        // Count ourselves as a reader before dropping the writer bit, so no writer can sneak in between
+        lock.state.fetch_add(READER, Ordering::Acquire);
+        lock.state
+            .fetch_and(!(WRITER | UPGRADEABLE), Ordering::Release);
+        SpinRwLockReadGuard::from(lock)
+    }
+
+    /// Like `downgrade`, but keeps the right to `upgrade` again later.
+    pub fn downgrade_to_upgradeable(s: Self) -> SpinRwLockUpgradeableGuard<'a, T, R> {
+        let lock = s.into_lock();
+
+        lock.state.fetch_or(UPGRADEABLE, Ordering::Acquire);
+        lock.state.fetch_and(!WRITER, Ordering::Release);
+        SpinRwLockUpgradeableGuard::from(lock)
+    }
+}
+
+impl<'a, T, R> Drop for SpinRwLockWriteGuard<'a, T, R> {
+    fn drop(&mut self) {
+        self.access.end();
+        // Also clears any stray UPGRADEABLE bit a failed `try_upgradeable_read` left while we held the lock
+        self.lock
+            .state
+            .fetch_and(!(WRITER | UPGRADEABLE), Ordering::Release);
+    }
+}
+
+impl<'a, T, R> Deref for SpinRwLockWriteGuard<'a, T, R> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+impl<'a, T, R> DerefMut for SpinRwLockWriteGuard<'a, T, R> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.data
+    }
+}
+
+pub struct SpinRwLockUpgradeableGuard<'a, T, R = Spin> {
+    lock: &'a SpinRwLock<T, R>,
+    data: &'a T,
+    access: Access,
+    _marker: GuardMarker,
+}
+
+impl<'a, T, R> SpinRwLockUpgradeableGuard<'a, T, R> {
+    pub(crate) fn from(l: &'a SpinRwLock<T, R>) -> Self {
+        let (data, access) = unsafe { l.data.read() };
+        Self {
+            lock: l,
+            data,
+            access,
+            _marker: PhantomData,
+        }
+    }
+
+    // See `SpinRwLockWriteGuard::into_lock`
+    fn into_lock(self) -> &'a SpinRwLock<T, R> {
+        let mut guard = mem::ManuallyDrop::new(self);
+        unsafe { ptr::drop_in_place(&mut guard.access) };
+        guard.lock
+    }
+}
+
+// Same as for `SpinRwLockWriteGuard`
+impl<'a, T, R: RelaxStrategy> SpinRwLockUpgradeableGuard<'a, T, R> {
+    /// Waits for the remaining readers to leave and becomes the writer. No other writer can get in first.
+    pub fn upgrade(s: Self) -> SpinRwLockWriteGuard<'a, T, R> {
+        let mut relax = R::default();
+        let mut guard = s;
+        loop {
+            guard = match Self::try_upgrade(guard) {
+                Ok(write_guard) => return write_guard,
+                Err(guard) => guard,
+            };
+            while guard.lock.state.load(Ordering::Relaxed) != UPGRADEABLE {
+                relax.relax();
+            }
+        }
+    }
+
+    /// Only succeeds if there are no plain readers left right now.
+    pub fn try_upgrade(mut s: Self) -> Result<SpinRwLockWriteGuard<'a, T, R>, Self> {
+        // Our read has to be over before a write can start, even if it turns out we weren't allowed to
+        s.access.end();
+        match s.lock.state.compare_exchange(
+            UPGRADEABLE,
+            WRITER,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => Ok(SpinRwLockWriteGuard::from(s.into_lock())),
+            Err(_) => {
+                s.access.resume();
+                Err(s)
+            }
+        }
+    }
+
+    pub fn downgrade(s: Self) -> SpinRwLockReadGuard<'a, T> {
+        let lock = s.into_lock();
+
+        lock.state.fetch_add(READER, Ordering::Acquire);
+        lock.state.fetch_and(!UPGRADEABLE, Ordering::Release);
+        SpinRwLockReadGuard::from(lock)
+    }
+}
+
+impl<'a, T, R> Drop for SpinRwLockUpgradeableGuard<'a, T, R> {
+    fn drop(&mut self) {
+        self.access.end();
+        self.lock.state.fetch_and(!UPGRADEABLE, Ordering::Release);
+    }
+}
+
+impl<'a, T, R> Deref for SpinRwLockUpgradeableGuard<'a, T, R> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+// Readers share `&T` across threads, so unlike a mutex `T` has to be `Sync` too
+unsafe impl<T: Send, R> Send for SpinRwLock<T, R> {}
+unsafe impl<T: Send + Sync, R> Sync for SpinRwLock<T, R> {}
+
+unsafe impl<T: Sync> Sync for SpinRwLockReadGuard<'_, T> {}
+unsafe impl<T: Sync, R> Sync for SpinRwLockWriteGuard<'_, T, R> {}
+unsafe impl<T: Sync, R> Sync for SpinRwLockUpgradeableGuard<'_, T, R> {}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread::spawn as thread_spawn;
+
+    #[test]
+    fn readers_share() {
+        let l = SpinRwLock::new(5);
+        let a = l.read();
+        let b = l.try_read().expect("readers don't exclude each other");
+        assert_eq!(10, *a + *b);
+        assert!(l.try_write().is_none());
+    }
+
+    #[test]
+    fn writer_excludes_everyone() {
+        let l = SpinRwLock::new(0);
+        let mut w = l.write();
+        *w += 1;
+        assert!(l.try_read().is_none());
+        assert!(l.try_write().is_none());
+        assert!(l.try_upgradeable_read().is_none());
+        drop(w);
+
+        // The failed `try_upgradeable_read` above must not have left the lock unusable
+        assert!(l.try_write().is_some());
+        assert_eq!(1, *l.read());
+    }
+
+    #[test]
+    fn upgradeable_coexists_with_readers_only() {
+        let l = SpinRwLock::new(0);
+        let u = l.upgradeable_read();
+        assert!(l.try_read().is_some());
+        assert!(l.try_upgradeable_read().is_none());
+        assert!(l.try_write().is_none());
+
+        let r = l.read();
+        let Err(u) = SpinRwLockUpgradeableGuard::try_upgrade(u) else {
+            panic!("upgraded while a reader was still in");
+        };
+        drop(r);
+
+        let mut w = SpinRwLockUpgradeableGuard::try_upgrade(u)
+            .ok()
+            .expect("readers are gone");
+        *w = 1;
+        let u = SpinRwLockWriteGuard::downgrade_to_upgradeable(w);
+        assert_eq!(1, *u);
+        assert!(l.try_read().is_some());
+
+        let r = SpinRwLockUpgradeableGuard::downgrade(u);
+        assert!(l.try_upgradeable_read().is_some());
+        assert!(l.try_write().is_none());
+        drop(r);
+        assert!(l.try_write().is_some());
+    }
+
+    #[test]
+    fn upgrade_waits_for_readers() {
+        let l = Arc::new(SpinRwLock::new(0));
+        let r = l.read();
+
+        let l2 = l.clone();
+        let upgrader = thread_spawn(move || {
+            let mut w = SpinRwLockUpgradeableGuard::upgrade(l2.upgradeable_read());
+            *w += 1;
+        });
+
+        // Can't have upgraded yet, we're still reading
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(0, *r);
+        drop(r);
+
+        upgrader.join().unwrap();
+        assert_eq!(1, *l.read());
+    }
+
+    #[test]
+    fn write_downgrade_keeps_writers_out() {
+        let l = SpinRwLock::new(0);
+        let r = SpinRwLockWriteGuard::downgrade(l.write());
+        assert!(l.try_write().is_none());
+        assert!(l.try_read().is_some());
+        drop(r);
+        assert!(l.try_write().is_some());
+    }
+
+    #[test]
+    fn concurrent_writers_and_readers() {
+        const THREADS: usize = 4;
+        const ITERATIONS: usize = 1000;
+
+        let l = Arc::new(SpinRwLock::new((0, 0)));
+        let handles: Vec<_> = (0..THREADS)
+            .map(|i| {
+                let l = l.clone();
+                thread_spawn(move || {
+                    for _ in 0..ITERATIONS {
+                        match i % 3 {
+                            0 => {
+                                let mut w = l.write();
+                                w.0 += 1;
+                                w.1 += 1;
+                            }
+                            1 => {
+                                let mut w =
+                                    SpinRwLockUpgradeableGuard::upgrade(l.upgradeable_read());
+                                w.0 += 1;
+                                w.1 += 1;
+                            }
+                            _ => {
+                                let r = l.read();
+                                assert_eq!(r.0, r.1);
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let writers = (0..THREADS).filter(|i| i % 3 != 2).count();
+        assert_eq!((writers * ITERATIONS, writers * ITERATIONS), *l.read());
+    }
+}
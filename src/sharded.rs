@@ -0,0 +1,271 @@
+// A concurrent hash map made of N ordinary `HashMap`s, each behind its own padded `SpinMutex`. A key always lives in
+// the shard its hash picks, so threads working on different keys mostly take different locks, and the padding keeps
+// those locks from false sharing with each other.
+//
+// Everything hands out guards rather than copies, so a `get` keeps its shard locked until the guard is dropped. Don't
+// hold one while touching another key, that other key may well live in the same shard.
+
+use core::borrow::Borrow;
+use core::hash::{BuildHasher, Hash};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::thread;
+
+use crate::{MappedSpinMutexGuard, PaddedSpinMutex, SpinMutex, SpinMutexGuard};
+
+pub struct SpinShardedMap<K, V, S = RandomState> {
+    shards: Box<[PaddedSpinMutex<HashMap<K, V, S>>]>,
+    hasher: S,
+    // log2 of the shard count, which is always a power of two
+    shift: u32,
+}
+
+impl<K, V> SpinShardedMap<K, V> {
+    /// A few shards per core, so even with every core busy in the map two threads rarely pick the same shard.
+    pub fn new() -> Self {
+        let cores = thread::available_parallelism().map_or(4, |n| n.get());
+        Self::with_shards(cores * 4)
+    }
+
+    /// Rounds `shards` up to a power of two.
+    pub fn with_shards(shards: usize) -> Self {
+        Self::with_shards_and_hasher(shards, RandomState::new())
+    }
+}
+
+impl<K, V> Default for SpinShardedMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S: BuildHasher + Clone> SpinShardedMap<K, V, S> {
+    pub fn with_shards_and_hasher(shards: usize, hasher: S) -> Self {
+        let shards = shards.max(1).next_power_of_two();
+        Self {
+            shards: (0..shards)
+                .map(|_| PaddedSpinMutex::new(HashMap::with_hasher(hasher.clone())))
+                .collect(),
+            hasher,
+            shift: shards.trailing_zeros(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> SpinShardedMap<K, V, S> {
+    fn shard_index<Q: Hash + ?Sized>(&self, key: &Q) -> usize {
+        if self.shift == 0 {
+            return 0;
+        }
+        // The shard maps hash with the same hasher, so picking shards off raw hash bits would leave every key in a
+        // shard agreeing on those bits, and `HashMap` uses them too. Fibonacci hashing mixes them up first.
+        let hash = self
+            .hasher
+            .hash_one(key)
+            .wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        (hash >> (u64::BITS - self.shift)) as usize
+    }
+
+    fn shard_for<Q: Hash + ?Sized>(&self, key: &Q) -> &SpinMutex<HashMap<K, V, S>> {
+        &self.shards[self.shard_index(key)]
+    }
+
+    /// Locks the shard `key` lives in, for doing several things to it (or to its neighbours) at once.
+    pub fn lock_shard_for<Q>(&self, key: &Q) -> SpinMutexGuard<'_, HashMap<K, V, S>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard_for(key).lock()
+    }
+
+    /// The value for `key`, with its shard locked for as long as the guard lives.
+    pub fn get<Q>(&self, key: &Q) -> Option<MappedSpinMutexGuard<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        SpinMutexGuard::filter_map(self.lock_shard_for(key), |shard| shard.get_mut(key)).ok()
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.lock_shard_for(key).contains_key(key)
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.shard_for(&key).lock().insert(key, value)
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.lock_shard_for(key).remove(key)
+    }
+
+    /// Locks `key`'s shard and hands back an entry for inserting or updating it in place.
+    pub fn entry(&self, key: K) -> ShardEntry<'_, K, V, S> {
+        ShardEntry {
+            shard: self.shard_for(&key).lock(),
+            key,
+        }
+    }
+
+    /// Only a snapshot: the shards are counted one after the other, not all at the same instant.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.lock().is_empty())
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+}
+
+/// A key in a `SpinShardedMap` along with its locked shard, see `SpinShardedMap::entry`.
+pub struct ShardEntry<'a, K, V, S> {
+    shard: SpinMutexGuard<'a, HashMap<K, V, S>>,
+    key: K,
+}
+
+impl<'a, K: Eq + Hash, V, S: BuildHasher> ShardEntry<'a, K, V, S> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Runs `f` on the value if there is one, like `hash_map::Entry::and_modify`.
+    pub fn and_modify(mut self, f: impl FnOnce(&mut V)) -> Self {
+        if let Some(value) = self.shard.get_mut(&self.key) {
+            f(value);
+        }
+        self
+    }
+
+    pub fn or_insert(self, default: V) -> MappedSpinMutexGuard<'a, V> {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with(self, default: impl FnOnce() -> V) -> MappedSpinMutexGuard<'a, V> {
+        let key = self.key;
+        SpinMutexGuard::map(self.shard, |shard| shard.entry(key).or_insert_with(default))
+    }
+
+    pub fn or_default(self) -> MappedSpinMutexGuard<'a, V>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread::spawn as thread_spawn;
+
+    #[test]
+    fn map_operations() {
+        let map = SpinShardedMap::with_shards(5);
+        assert_eq!(8, map.shard_count());
+        assert!(map.is_empty());
+
+        assert_eq!(None, map.insert("a".to_string(), 1));
+        assert_eq!(Some(1), map.insert("a".to_string(), 2));
+        map.insert("b".to_string(), 3);
+        assert_eq!(2, map.len());
+
+        // Borrowed lookups, like `HashMap`
+        assert_eq!(2, *map.get("a").unwrap());
+        *map.get("b").unwrap() += 10;
+        assert_eq!(13, *map.get("b").unwrap());
+        assert!(map.get("c").is_none());
+        assert!(map.contains_key("a"));
+
+        assert_eq!(Some(2), map.remove("a"));
+        assert_eq!(None, map.remove("a"));
+        assert_eq!(1, map.len());
+    }
+
+    #[test]
+    fn entry_inserts_or_updates_in_place() {
+        let map = SpinShardedMap::new();
+        *map.entry("x").or_insert(0) += 1;
+        *map.entry("x").or_insert(0) += 1;
+        assert_eq!(2, *map.get("x").unwrap());
+
+        let entry = map.entry("x").and_modify(|v| *v *= 10);
+        assert_eq!("x", *entry.key());
+        assert_eq!(20, *entry.or_default());
+
+        map.entry("y")
+            .and_modify(|_| panic!("there's nothing to modify"));
+        assert_eq!(7, *map.entry("z").or_insert_with(|| 7));
+    }
+
+    #[test]
+    fn lock_shard_for_is_the_shard_the_key_lives_in() {
+        let map = SpinShardedMap::with_shards(16);
+        for key in 0..100 {
+            map.insert(key, key * 2);
+        }
+        for key in 0..100 {
+            let shard = map.lock_shard_for(&key);
+            assert_eq!(Some(&(key * 2)), shard.get(&key));
+        }
+
+        // And writes through it are what `get` sees afterwards
+        map.lock_shard_for(&7).insert(7, 0);
+        assert_eq!(0, *map.get(&7).unwrap());
+    }
+
+    #[test]
+    fn keys_spread_over_shards() {
+        let map = SpinShardedMap::with_shards(8);
+        for key in 0..1_000 {
+            map.insert(key, ());
+        }
+        // A terrible spread would put most of them in one place
+        for shard in map.shards.iter() {
+            let len = shard.lock().len();
+            assert!((50..250).contains(&len), "{len} keys in one shard");
+        }
+    }
+
+    #[test]
+    fn counting_from_many_threads() {
+        const THREADS: usize = 4;
+        const KEYS: usize = 64;
+        const ROUNDS: usize = 500;
+
+        let map = Arc::new(SpinShardedMap::new());
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let map = map.clone();
+                thread_spawn(move || {
+                    for _ in 0..ROUNDS {
+                        for key in 0..KEYS {
+                            *map.entry(key).or_insert(0) += 1;
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(KEYS, map.len());
+        for key in 0..KEYS {
+            assert_eq!(THREADS * ROUNDS, *map.get(&key).unwrap());
+        }
+    }
+}
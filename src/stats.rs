@@ -0,0 +1,175 @@
+// Per-mutex contention counters behind the `stats` feature, for finding out which locks are actually hot. They live in
+// the mutex's `LockWord` so every way of locking and unlocking, guards of all kinds included, keeps them up to date.
+//
+// All plain `Relaxed` counters outside the lock protocol (and outside loom's view). `held_since` is only ever touched
+// by the current holder, so the lock's own Acquire/Release already orders it between holders.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use crate::SpinMutex;
+
+/// A snapshot of a `SpinMutex`'s counters since it was created or last reset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LockStats {
+    /// Every successful lock, including `try_lock` and relocking after `SpinMutexGuard::unlocked`.
+    pub acquisitions: u64,
+    /// Blocking locks that found the mutex held and had to spin.
+    pub contended_acquisitions: u64,
+    /// Times round the relax loop, over all contended acquisitions.
+    pub spin_iterations: u64,
+    pub max_hold_time: Duration,
+    pub max_wait_time: Duration,
+}
+
+impl<T: ?Sized, R> SpinMutex<T, R> {
+    pub fn stats(&self) -> LockStats {
+        let stats = &self.raw.word.stats;
+        LockStats {
+            acquisitions: stats.acquisitions.load(Ordering::Relaxed),
+            contended_acquisitions: stats.contended.load(Ordering::Relaxed),
+            spin_iterations: stats.spins.load(Ordering::Relaxed),
+            max_hold_time: Duration::from_nanos(stats.max_hold_nanos.load(Ordering::Relaxed)),
+            max_wait_time: Duration::from_nanos(stats.max_wait_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Zeroes the counters. A hold that's in progress still counts once it ends.
+    pub fn reset_stats(&self) {
+        let stats = &self.raw.word.stats;
+        stats.acquisitions.store(0, Ordering::Relaxed);
+        stats.contended.store(0, Ordering::Relaxed);
+        stats.spins.store(0, Ordering::Relaxed);
+        stats.max_hold_nanos.store(0, Ordering::Relaxed);
+        stats.max_wait_nanos.store(0, Ordering::Relaxed);
+    }
+}
+
+pub(crate) struct Stats {
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+    spins: AtomicU64,
+    max_hold_nanos: AtomicU64,
+    max_wait_nanos: AtomicU64,
+    held_since: AtomicU64,
+}
+
+// Timestamps are nanoseconds since the first one anyone took, so they fit in an `AtomicU64`
+fn now() -> u64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+impl Stats {
+    pub(crate) const fn new() -> Self {
+        Self {
+            acquisitions: AtomicU64::new(0),
+            contended: AtomicU64::new(0),
+            spins: AtomicU64::new(0),
+            max_hold_nanos: AtomicU64::new(0),
+            max_wait_nanos: AtomicU64::new(0),
+            held_since: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn acquired(&self) {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        self.held_since.store(now(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn released(&self) {
+        let held = now().saturating_sub(self.held_since.load(Ordering::Relaxed));
+        self.max_hold_nanos.fetch_max(held, Ordering::Relaxed);
+    }
+}
+
+/// The waiting side of one blocking `acquire`. Stays idle, without reading the clock, unless the first attempt fails.
+#[derive(Default)]
+pub(crate) struct Wait {
+    started: Option<u64>,
+    spins: u64,
+}
+
+impl Wait {
+    pub(crate) fn start(&mut self) {
+        self.started.get_or_insert_with(now);
+    }
+
+    pub(crate) fn spun(&mut self) {
+        self.spins += 1;
+    }
+
+    pub(crate) fn finish(self, stats: &Stats) {
+        if let Some(started) = self.started {
+            stats.contended.fetch_add(1, Ordering::Relaxed);
+            stats.spins.fetch_add(self.spins, Ordering::Relaxed);
+            stats
+                .max_wait_nanos
+                .fetch_max(now().saturating_sub(started), Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+    use crate::SpinMutexGuard;
+    use std::sync::Arc;
+    use std::thread::{sleep, spawn as thread_spawn};
+
+    #[test]
+    fn uncontended_locks_only_count_acquisitions() {
+        let m = SpinMutex::new(0);
+        *m.lock() += 1;
+        *m.try_lock().unwrap() += 1;
+        let mapped = SpinMutexGuard::map(m.lock(), |v| v);
+        drop(mapped);
+
+        let stats = m.stats();
+        assert_eq!(3, stats.acquisitions);
+        assert_eq!(0, stats.contended_acquisitions);
+        assert_eq!(0, stats.spin_iterations);
+        assert_eq!(Duration::ZERO, stats.max_wait_time);
+    }
+
+    #[test]
+    fn contended_lock_records_wait_and_hold() {
+        const HOLD: Duration = Duration::from_millis(20);
+
+        let m = Arc::new(SpinMutex::new(0));
+        let guard = m.lock();
+        let m2 = m.clone();
+        let waiter = thread_spawn(move || *m2.lock() += 1);
+
+        // Give the waiter time to find the lock taken before letting go
+        sleep(HOLD);
+        drop(guard);
+        waiter.join().unwrap();
+
+        let stats = m.stats();
+        assert_eq!(2, stats.acquisitions);
+        assert_eq!(1, stats.contended_acquisitions);
+        assert!(stats.spin_iterations > 0);
+        assert!(stats.max_hold_time >= HOLD, "{stats:?}");
+        assert!(stats.max_wait_time > Duration::ZERO, "{stats:?}");
+        assert!(stats.max_wait_time <= stats.max_hold_time, "{stats:?}");
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let m = SpinMutex::new(());
+        drop(m.lock());
+        drop(m.lock());
+        m.reset_stats();
+        assert_eq!(LockStats::default(), m.stats());
+
+        // Resetting mid-hold still counts the hold once it ends
+        let guard = m.lock();
+        m.reset_stats();
+        sleep(Duration::from_millis(5));
+        drop(guard);
+        assert!(m.stats().max_hold_time >= Duration::from_millis(5));
+    }
+}
@@ -0,0 +1,271 @@
+// Everything the locks touch that loom needs to see goes through here, so building with `--features loom` swaps in
+// loom's model-checked versions without the rest of the crate having to care.
+
+#[cfg(not(feature = "loom"))]
+pub(crate) use core::{
+    hint,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, AtomicUsize, Ordering},
+};
+#[cfg(feature = "loom")]
+pub(crate) use loom::{
+    hint,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, AtomicUsize, Ordering},
+    thread, thread_local,
+};
+#[cfg(all(feature = "std", not(feature = "loom")))]
+pub(crate) use std::{thread, thread_local};
+
+// Not a loom atomic on purpose: it only hands out ids and never orders anything a lock protects. Starts at 1 so 0 can
+// mean "nobody", e.g. an unlocked `ReentrantSpinMutex`.
+#[cfg(feature = "std")]
+static NEXT_THREAD_ID: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(1);
+
+#[cfg(feature = "std")]
+thread_local! {
+    // Not the address of some thread-local: that can be handed to a new thread once the old one exits, and a guard
+    // leaked by the old thread would then be mistaken for the new one's
+    #[allow(clippy::missing_const_for_thread_local)]
+    static THREAD_ID: usize = NEXT_THREAD_ID.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+}
+
+/// Unique for the life of the process, unlike `std::thread::ThreadId` it's a plain `usize` that fits in an atomic.
+#[cfg(feature = "std")]
+pub(crate) fn current_thread_id() -> usize {
+    THREAD_ID.with(|id| *id)
+}
+
+/// Per-object side data for objects that don't have room for it themselves, e.g. the futures parked on a mutex. Keyed
+/// by the object's address and spread over a fixed set of buckets, so unrelated objects rarely share a bucket lock.
+///
+/// Each bucket is a std `Mutex` rather than one of ours, so that a bucket's own unlock can never need to wake anything
+/// or report anything.
+// Only `future`, `watchdog` and the portable half of `hybrid` have anything to keep in one
+#[cfg(feature = "std")]
+#[cfg_attr(
+    not(any(
+        feature = "async",
+        feature = "watchdog",
+        not(target_os = "linux"),
+        test
+    )),
+    allow(dead_code)
+)]
+pub(crate) struct AddrTable<V> {
+    buckets: [std::sync::Mutex<std::vec::Vec<(usize, V)>>; ADDR_TABLE_BUCKETS],
+}
+
+#[cfg(feature = "std")]
+#[cfg_attr(
+    not(any(
+        feature = "async",
+        feature = "watchdog",
+        not(target_os = "linux"),
+        test
+    )),
+    allow(dead_code)
+)]
+const ADDR_TABLE_BUCKETS: usize = 64;
+
+#[cfg(feature = "std")]
+#[cfg_attr(
+    not(any(
+        feature = "async",
+        feature = "watchdog",
+        not(target_os = "linux"),
+        test
+    )),
+    allow(dead_code)
+)]
+impl<V> AddrTable<V> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            buckets: [const { std::sync::Mutex::new(std::vec::Vec::new()) }; ADDR_TABLE_BUCKETS],
+        }
+    }
+
+    /// Locks the bucket `addr` falls in. Other addresses share it, so every entry carries the address it's for.
+    pub(crate) fn bucket(
+        &self,
+        addr: usize,
+    ) -> std::sync::MutexGuard<'_, std::vec::Vec<(usize, V)>> {
+        // Fibonacci hashing, so objects that sit at regular strides in memory don't all land in the same bucket
+        let hash = addr.wrapping_mul(0x9E37_79B9_7F4A_7C15_u64 as usize);
+        let bucket = &self.buckets[hash >> (usize::BITS - ADDR_TABLE_BUCKETS.trailing_zeros())];
+        bucket.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Loom's primitives can't be built in a const context, so constructors are only `const` without loom
+macro_rules! const_fn {
+    ($(#[$attr:meta])* $vis:vis fn $($rest:tt)*) => {
+        #[cfg(not(feature = "loom"))]
+        $(#[$attr])* $vis const fn $($rest)*
+        #[cfg(feature = "loom")]
+        $(#[$attr])* $vis fn $($rest)*
+    };
+}
+pub(crate) use const_fn;
+
+#[cfg(not(feature = "loom"))]
+pub(crate) struct UnsafeCell<T: ?Sized>(core::cell::UnsafeCell<T>);
+
+#[cfg(not(feature = "loom"))]
+impl<T> UnsafeCell<T> {
+    pub(crate) const fn new(data: T) -> Self {
+        Self(core::cell::UnsafeCell::new(data))
+    }
+
+    pub(crate) fn into_inner(self) -> T {
+        self.0.into_inner()
+    }
+}
+
+#[cfg(not(feature = "loom"))]
+impl<T: ?Sized> UnsafeCell<T> {
+    pub(crate) fn get_mut(&mut self) -> &mut T {
+        self.0.get_mut()
+    }
+
+    pub(crate) fn get(&self) -> *mut T {
+        self.0.get()
+    }
+
+    // SAFETY: the caller has exclusive access to the data until the `Access` is ended or dropped
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn write(&self) -> (&mut T, Access) {
+        (&mut *self.0.get(), Access)
+    }
+
+    // SAFETY: nobody writes to the data until the `Access` is ended or dropped
+    pub(crate) unsafe fn read(&self) -> (&T, Access) {
+        (&*self.0.get(), Access)
+    }
+}
+
+/// Held by every guard next to its reference to the data, and ended just before the lock is released. Without loom
+/// there's nothing to keep track of.
+#[cfg(not(feature = "loom"))]
+pub(crate) struct Access;
+
+#[cfg(not(feature = "loom"))]
+impl Access {
+    pub(crate) fn end(&mut self) {}
+
+    pub(crate) fn resume(&mut self) {}
+
+    pub(crate) fn leak(self) {}
+}
+
+#[cfg(feature = "loom")]
+pub(crate) struct UnsafeCell<T: ?Sized>(loom::cell::UnsafeCell<T>);
+
+#[cfg(feature = "loom")]
+impl<T> UnsafeCell<T> {
+    pub(crate) fn new(data: T) -> Self {
+        Self(loom::cell::UnsafeCell::new(data))
+    }
+
+    pub(crate) fn into_inner(self) -> T {
+        self.0.into_inner()
+    }
+}
+
+#[cfg(feature = "loom")]
+impl<T: ?Sized> UnsafeCell<T> {
+    pub(crate) fn get_mut(&mut self) -> &mut T {
+        // Having `&mut self` already proves exclusive access, loom just gets to record it
+        self.0.with_mut(|ptr| unsafe { &mut *ptr })
+    }
+
+    // Only for handing out a raw pointer, loom checks the access at the moment this is called and nothing after
+    pub(crate) fn get(&self) -> *mut T {
+        self.0.with_mut(|ptr| ptr)
+    }
+
+    // Loom counts the data as being written to for as long as the `Access` is alive, so every read and write made
+    // through the guard gets checked against the other threads, not just the moment the guard was created
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn write(&self) -> (&mut T, Access) {
+        let ptr = self.0.get_mut();
+        let data = &mut *ptr.with(|ptr| ptr);
+        (data, Access::open(&self.0, Tracked::Write(Some(ptr))))
+    }
+
+    pub(crate) unsafe fn read(&self) -> (&T, Access) {
+        let ptr = self.0.get();
+        let data = &*ptr.with(|ptr| ptr);
+        (data, Access::open(&self.0, Tracked::Read(Some(ptr))))
+    }
+}
+
+#[cfg(feature = "loom")]
+pub(crate) struct Access(Box<dyn Track>);
+
+// Loom's pointers are typed, but a mapped guard no longer knows the type it was locked with, so they're kept behind a
+// trait object. Dropping one only tells loom the access is over, it never touches the data, so it doesn't matter if
+// the lifetimes say the data could be gone by then.
+#[cfg(feature = "loom")]
+trait Track {
+    fn end(&mut self);
+    fn resume(&mut self);
+}
+
+#[cfg(feature = "loom")]
+enum Tracked<T: ?Sized> {
+    Write(Option<loom::cell::MutPtr<T>>),
+    Read(Option<loom::cell::ConstPtr<T>>),
+}
+
+#[cfg(feature = "loom")]
+struct Tracker<T: ?Sized> {
+    // Outlives the guard, like the mutex it's in
+    cell: *const loom::cell::UnsafeCell<T>,
+    tracked: Tracked<T>,
+}
+
+#[cfg(feature = "loom")]
+impl<T: ?Sized> Track for Tracker<T> {
+    fn end(&mut self) {
+        match &mut self.tracked {
+            Tracked::Write(ptr) => drop(ptr.take()),
+            Tracked::Read(ptr) => drop(ptr.take()),
+        }
+    }
+
+    fn resume(&mut self) {
+        let cell = unsafe { &*self.cell };
+        match &mut self.tracked {
+            Tracked::Write(ptr) => *ptr = Some(cell.get_mut()),
+            Tracked::Read(ptr) => *ptr = Some(cell.get()),
+        }
+    }
+}
+
+#[cfg(feature = "loom")]
+impl Access {
+    fn open<T: ?Sized>(cell: &loom::cell::UnsafeCell<T>, tracked: Tracked<T>) -> Self {
+        let tracker: Box<dyn Track + '_> = Box::new(Tracker { cell, tracked });
+        // SAFETY: see `Track`, only the lifetime changes
+        Self(unsafe { core::mem::transmute::<Box<dyn Track + '_>, Box<dyn Track>>(tracker) })
+    }
+
+    // For data that stays locked for good
+    pub(crate) fn leak(self) {
+        core::mem::forget(self);
+    }
+
+    pub(crate) fn end(&mut self) {
+        self.0.end();
+    }
+
+    // Only once the lock is ours again
+    pub(crate) fn resume(&mut self) {
+        self.0.resume();
+    }
+}
+
+// Just loom's bookkeeping, and loom runs every model thread on the one OS thread anyway
+#[cfg(feature = "loom")]
+unsafe impl Send for Access {}
+#[cfg(feature = "loom")]
+unsafe impl Sync for Access {}
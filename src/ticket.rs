@@ -0,0 +1,165 @@
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut, Drop};
+
+use crate::relax::{RelaxStrategy, Spin};
+use crate::sync::{const_fn, Access, AtomicUsize, Ordering, UnsafeCell};
+use crate::GuardMarker;
+
+/// A fair spin lock: every locker takes a ticket and waits for it to be called, so the lock is handed out in the
+/// exact order `lock` was called in.
+///
+/// There's no `try_lock_for`, since a ticket can't be given back once taken without stalling everyone behind it.
+pub struct TicketMutex<T, R = Spin> {
+    next_ticket: AtomicUsize,
+    now_serving: AtomicUsize,
+    data: UnsafeCell<T>,
+    relax: PhantomData<R>,
+}
+
+impl<T> TicketMutex<T> {
+    const_fn! {
+        pub fn new(data: T) -> Self {
+            Self::with_relax(data)
+        }
+    }
+}
+
+impl<'a, T, R: RelaxStrategy> TicketMutex<T, R> {
+    const_fn! {
+        pub fn with_relax(data: T) -> Self {
+            Self {
+                next_ticket: AtomicUsize::new(0),
+                now_serving: AtomicUsize::new(0),
+                data: UnsafeCell::new(data),
+                relax: PhantomData,
+            }
+        }
+    }
+
+    pub fn lock(&'a self) -> TicketMutexGuard<'a, T> {
+        // Only the counter needs to be atomic here, the ticket number itself doesn't publish anything
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+
+        let mut relax = R::default();
+        // Acquire pairs with the Release in `TicketMutexGuard::drop` of whoever held the ticket before ours
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            relax.relax();
+        }
+
+        TicketMutexGuard::from(self, ticket)
+    }
+
+    /// Only succeeds if nobody holds the lock *and* nobody is queued for it, so it never jumps the line.
+    pub fn try_lock(&'a self) -> Option<TicketMutexGuard<'a, T>> {
+        let ticket = self.now_serving.load(Ordering::Acquire);
+        self.next_ticket
+            .compare_exchange(
+                ticket,
+                ticket.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .ok()
+            .map(|_| TicketMutexGuard::from(self, ticket))
+    }
+}
+
+pub struct TicketMutexGuard<'a, T> {
+    now_serving: &'a AtomicUsize,
+    ticket: usize,
+    data: &'a mut T,
+    access: Access,
+    _marker: GuardMarker,
+}
+
+impl<'a, T> TicketMutexGuard<'a, T> {
+    pub(crate) fn from<R>(m: &'a TicketMutex<T, R>, ticket: usize) -> Self {
+        let (data, access) = unsafe { m.data.write() };
+        Self {
+            now_serving: &m.now_serving,
+            ticket,
+            data,
+            access,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T> Drop for TicketMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.access.end();
+        // We're the only one allowed to move `now_serving` while we hold the lock, so no read-modify-write needed
+        self.now_serving
+            .store(self.ticket.wrapping_add(1), Ordering::Release);
+    }
+}
+
+impl<'a, T> Deref for TicketMutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+impl<'a, T> DerefMut for TicketMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.data
+    }
+}
+
+unsafe impl<T: Send, R> Send for TicketMutex<T, R> {}
+unsafe impl<T: Send, R> Sync for TicketMutex<T, R> {}
+
+unsafe impl<T: Sync> Sync for TicketMutexGuard<'_, T> {}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread::{spawn as thread_spawn, yield_now};
+
+    #[test]
+    fn try_lock_respects_holder_and_queue() {
+        let m = Arc::new(TicketMutex::new(0));
+        let guard = m.lock();
+        assert!(m.try_lock().is_none());
+
+        // Someone queued behind us must still be served before a later `try_lock`
+        let m2 = m.clone();
+        let waiter = thread_spawn(move || *m2.lock() += 1);
+        while m.next_ticket.load(Ordering::Relaxed) != 2 {
+            yield_now();
+        }
+        drop(guard);
+        waiter.join().unwrap();
+
+        let guard = m.try_lock().expect("queue is empty now");
+        assert_eq!(1, *guard);
+    }
+
+    #[test]
+    fn hands_off_in_fifo_order() {
+        const WAITERS: usize = 8;
+
+        let m = Arc::new(TicketMutex::new(Vec::new()));
+        let guard = m.lock();
+
+        // Queue the waiters up one at a time, so the order they took tickets in is known
+        let handles: Vec<_> = (0..WAITERS)
+            .map(|i| {
+                let m2 = m.clone();
+                let handle = thread_spawn(move || m2.lock().push(i));
+                while m.next_ticket.load(Ordering::Relaxed) != i + 2 {
+                    yield_now();
+                }
+                handle
+            })
+            .collect();
+
+        drop(guard);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!((0..WAITERS).collect::<Vec<_>>(), *m.lock());
+    }
+}
@@ -0,0 +1,384 @@
+// Debug aid behind the `watchdog` feature: once a `lock` has spun past a configurable threshold, a user-registered hook
+// gets told which lock it is, who's waiting, who holds it and for how long, so a stuck holder shows up as a log line (or
+// an abort) instead of a core quietly pinned at 100%.
+//
+// Knowing the holder means noting it down on every acquire, so the lock word carries the holder's thread id, which is
+// just one relaxed store either way. Turning that id back into a `Thread` for the report needs a table of threads,
+// which a thread only joins the first time it takes a lock while a hook is set, so without one nothing shared is
+// touched at all.
+
+use core::cell::Cell;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+use crate::sync::{current_thread_id, AddrTable};
+use crate::LockWord;
+
+/// When `lock` starts calling the watchdog hook. It's called again every time the same amount passes once more, for
+/// as long as the lock stays out of reach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpinThreshold {
+    /// After this many times round the relax loop.
+    Spins(u64),
+    /// After this much wall time, checked every few spins so `Instant::now` stays off the hot path.
+    Elapsed(Duration),
+}
+
+/// What the watchdog hook gets told about a `lock` that has been spinning for too long.
+#[derive(Debug)]
+pub struct StuckLock<'a> {
+    /// Address of the mutex's lock word. Only good for telling mutexes apart.
+    pub mutex: usize,
+    pub waiter: &'a Thread,
+    /// Whoever took the lock, even if the guard has since been moved to another thread with `send_guard`. `None` if
+    /// they let go just then, or took it before any hook was set.
+    pub owner: Option<&'a Thread>,
+    pub elapsed: Duration,
+    pub spins: u64,
+}
+
+impl fmt::Display for StuckLock<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn name(t: &Thread) -> String {
+            format!("{:?} ({})", t.id(), t.name().unwrap_or("unnamed"))
+        }
+        write!(
+            f,
+            "thread {} has spun {} times over {:?} waiting for the mutex at {:#x}, held by ",
+            name(self.waiter),
+            self.spins,
+            self.elapsed,
+            self.mutex
+        )?;
+        match self.owner {
+            Some(owner) => write!(f, "thread {}", name(owner)),
+            None => f.write_str("an unknown thread"),
+        }
+    }
+}
+
+type Hook = Arc<dyn Fn(&StuckLock<'_>) + Send + Sync>;
+
+// Checked on every spin, so it's a plain flag in front of the hook itself
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static WATCHDOG: RwLock<Option<(SpinThreshold, Hook)>> = RwLock::new(None);
+
+/// Calls `hook` whenever a `SpinMutex::lock` has spun past `threshold`, replacing any hook set before. The hook runs
+/// on the waiting thread, and can panic or abort to stop the spinning.
+pub fn set_spin_watchdog<F>(threshold: SpinThreshold, hook: F)
+where
+    F: Fn(&StuckLock<'_>) + Send + Sync + 'static,
+{
+    *WATCHDOG.write().unwrap_or_else(|e| e.into_inner()) = Some((threshold, Arc::new(hook)));
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn clear_spin_watchdog() {
+    ENABLED.store(false, Ordering::Relaxed);
+    *WATCHDOG.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+// How often an `Elapsed` threshold looks at the clock
+const CLOCK_CHECK_SPINS: u64 = 256;
+
+/// One `acquire`'s worth of watching, set up lazily on the first spin so an uncontended lock never pays for it.
+pub(crate) struct Watch<'a> {
+    lock: &'a LockWord,
+    spins: u64,
+    armed: Option<Armed>,
+}
+
+struct Armed {
+    threshold: SpinThreshold,
+    hook: Hook,
+    started: Instant,
+    // `None` once the next report is further out than a `u64` or an `Instant` can count, so never. A huge threshold
+    // is the natural way to say so, the same as a huge timeout for `try_lock_for`.
+    next_report: Option<u64>,
+    next_report_at: Option<Instant>,
+}
+
+impl<'a> Watch<'a> {
+    pub(crate) fn new(lock: &'a LockWord) -> Self {
+        Self {
+            lock,
+            spins: 0,
+            armed: None,
+        }
+    }
+
+    pub(crate) fn spun(&mut self) {
+        self.spins = self.spins.saturating_add(1);
+        if !ENABLED.load(Ordering::Relaxed) {
+            return;
+        }
+        let armed = match &mut self.armed {
+            Some(armed) => armed,
+            None => {
+                let Some((threshold, hook)) =
+                    WATCHDOG.read().unwrap_or_else(|e| e.into_inner()).clone()
+                else {
+                    return;
+                };
+                let started = Instant::now();
+                let (next_report, next_report_at) = match threshold {
+                    SpinThreshold::Spins(n) => (self.spins.checked_add(n.max(1)), None),
+                    SpinThreshold::Elapsed(d) => (None, started.checked_add(d)),
+                };
+                self.armed.insert(Armed {
+                    threshold,
+                    hook,
+                    started,
+                    next_report,
+                    next_report_at,
+                })
+            }
+        };
+
+        let due = match armed.threshold {
+            SpinThreshold::Spins(n) => {
+                let due = armed.next_report.is_some_and(|at| self.spins >= at);
+                if due {
+                    armed.next_report = armed.next_report.and_then(|at| at.checked_add(n.max(1)));
+                }
+                due
+            }
+            SpinThreshold::Elapsed(d) => {
+                let due = self.spins.is_multiple_of(CLOCK_CHECK_SPINS)
+                    && armed.next_report_at.is_some_and(|at| Instant::now() >= at);
+                if due {
+                    armed.next_report_at = armed.next_report_at.and_then(|at| at.checked_add(d));
+                }
+                due
+            }
+        };
+        if !due {
+            return;
+        }
+
+        let owner = owner_of(self.lock);
+        let waiter = thread::current();
+        (armed.hook)(&StuckLock {
+            mutex: addr(self.lock),
+            waiter: &waiter,
+            owner: owner.as_ref(),
+            elapsed: armed.started.elapsed(),
+            spins: self.spins,
+        });
+    }
+}
+
+// Not a loom atomic: like `deadlock::LockId` it only says who to blame, it never orders anything the lock protects
+pub(crate) struct Owner(AtomicUsize);
+
+impl Owner {
+    pub(crate) const fn new() -> Self {
+        Self(AtomicUsize::new(0))
+    }
+
+    fn get(&self) -> Option<usize> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            id => Some(id),
+        }
+    }
+}
+
+// Keyed by `current_thread_id`
+static THREADS: AddrTable<Thread> = AddrTable::new();
+
+// Takes the thread back out of `THREADS` when it exits, so the table only ever holds live threads
+struct Registration(Cell<Option<usize>>);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Some(id) = self.0.get() {
+            let mut threads = THREADS.bucket(id);
+            if let Some(i) = threads.iter().position(|(t, _)| *t == id) {
+                threads.swap_remove(i);
+            }
+        }
+    }
+}
+
+std::thread_local! {
+    static REGISTRATION: Registration = const { Registration(Cell::new(None)) };
+}
+
+fn register(id: usize) {
+    // Fails once the thread is exiting and its registration is gone, by then there's nothing worth reporting anyway
+    let _ = REGISTRATION.try_with(|r| {
+        if r.0.get().is_none() {
+            r.0.set(Some(id));
+            THREADS.bucket(id).push((id, thread::current()));
+        }
+    });
+}
+
+fn addr(lock: &LockWord) -> usize {
+    lock as *const LockWord as usize
+}
+
+pub(crate) fn acquired(lock: &LockWord) {
+    let id = current_thread_id();
+    lock.owner.0.store(id, Ordering::Relaxed);
+    if ENABLED.load(Ordering::Relaxed) {
+        register(id);
+    }
+}
+
+pub(crate) fn released(lock: &LockWord) {
+    lock.owner.0.store(0, Ordering::Relaxed);
+}
+
+fn owner_of(lock: &LockWord) -> Option<Thread> {
+    let id = lock.owner.get()?;
+    THREADS
+        .bucket(id)
+        .iter()
+        .find(|(t, _)| *t == id)
+        .map(|(_, thread)| thread.clone())
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+    use crate::SpinMutex;
+    use std::sync::{mpsc, Mutex};
+    use std::thread::Builder;
+
+    // The hook is global, so the tests here mustn't swap it out from under each other
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    // Holds a lock while another thread spins on it, and returns the owner, elapsed time and spin count the hook saw
+    fn report_for(threshold: SpinThreshold) -> (Option<thread::ThreadId>, Duration, u64) {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let m = Arc::new(SpinMutex::new(0));
+
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let ours = addr(&m.raw.word);
+        // Other tests' locks spin too, only listen for ours
+        set_spin_watchdog(threshold, move |stuck| {
+            if stuck.mutex == ours {
+                let _ = tx.lock().unwrap().send((
+                    stuck.waiter.name().map(str::to_owned),
+                    stuck.owner.map(Thread::id),
+                    stuck.elapsed,
+                    stuck.spins,
+                    stuck.to_string(),
+                ));
+            }
+        });
+        // Only now, a thread that took its locks before there was a hook to report to can't be named
+        let guard = m.lock();
+
+        let m2 = m.clone();
+        let waiter = Builder::new()
+            .name("stuck-waiter".into())
+            .spawn(move || *m2.lock() += 1)
+            .unwrap();
+
+        let (name, owner, elapsed, spins, message) = rx.recv().unwrap();
+        clear_spin_watchdog();
+        drop(guard);
+        waiter.join().unwrap();
+
+        assert_eq!(Some("stuck-waiter"), name.as_deref());
+        assert!(message.contains("stuck-waiter"), "{message}");
+        (owner, elapsed, spins)
+    }
+
+    #[test]
+    fn spin_threshold_names_waiter_and_owner() {
+        let (owner, _, spins) = report_for(SpinThreshold::Spins(10_000));
+        assert_eq!(Some(thread::current().id()), owner);
+        assert!(spins >= 10_000);
+    }
+
+    #[test]
+    fn elapsed_threshold_waits_long_enough() {
+        let (owner, elapsed, _) = report_for(SpinThreshold::Elapsed(Duration::from_millis(20)));
+        assert_eq!(Some(thread::current().id()), owner);
+        assert!(elapsed >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn huge_thresholds_mean_never() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        for threshold in [
+            SpinThreshold::Spins(u64::MAX),
+            SpinThreshold::Elapsed(Duration::MAX),
+        ] {
+            let m = Arc::new(SpinMutex::new(0));
+            let guard = m.lock();
+            let ours = addr(&m.raw.word);
+            let reported = Arc::new(AtomicBool::new(false));
+            let reported2 = reported.clone();
+            set_spin_watchdog(threshold, move |stuck| {
+                if stuck.mutex == ours {
+                    reported2.store(true, Ordering::Relaxed);
+                }
+            });
+
+            let m2 = m.clone();
+            let waiter = thread::spawn(move || *m2.lock() += 1);
+            thread::sleep(Duration::from_millis(50));
+            drop(guard);
+            // Would have panicked working out the first deadline
+            waiter.join().unwrap();
+            clear_spin_watchdog();
+            assert!(!reported.load(Ordering::Relaxed), "{threshold:?}");
+        }
+    }
+
+    #[test]
+    fn owner_follows_lock_and_unlock() {
+        let m = SpinMutex::new(());
+        assert_eq!(None, m.raw.word.owner.get());
+        let guard = m.lock();
+        assert_eq!(Some(current_thread_id()), m.raw.word.owner.get());
+        drop(guard);
+        assert_eq!(None, m.raw.word.owner.get());
+    }
+
+    #[test]
+    fn leaked_guard_does_not_name_the_owner_of_the_next_mutex_at_its_address() {
+        // Same slot every time round, so each mutex most likely reuses the last one's address
+        for _ in 0..3 {
+            let m = SpinMutex::new(0);
+            assert_eq!(None, m.raw.word.owner.get());
+            crate::SpinMutexGuard::leak(m.lock());
+        }
+    }
+
+    #[test]
+    fn threads_only_register_while_a_hook_is_set() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let m = SpinMutex::new(());
+        let lock_and_check = || {
+            thread::scope(|s| {
+                s.spawn(|| {
+                    drop(m.lock());
+                    let id = current_thread_id();
+                    (id, THREADS.bucket(id).iter().any(|(t, _)| *t == id))
+                })
+                .join()
+                .unwrap()
+            })
+        };
+
+        let (_, registered) = lock_and_check();
+        assert!(!registered);
+
+        set_spin_watchdog(SpinThreshold::Spins(u64::MAX), |_| {});
+        let (id, registered) = lock_and_check();
+        clear_spin_watchdog();
+        assert!(registered);
+        // And gone again now that it has exited
+        assert!(!THREADS.bucket(id).iter().any(|(t, _)| *t == id));
+    }
+}
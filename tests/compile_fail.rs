@@ -0,0 +1,25 @@
+// The expected compiler output lives next to each case in `tests/ui`. If rustc changes its wording, regenerate it with
+// `TRYBUILD=overwrite cargo test --test compile_fail` and check the diff still describes the same error.
+#![cfg(not(feature = "loom"))]
+
+#[test]
+fn unsound_sharing_is_rejected() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/mutex_*.rs");
+    t.compile_fail("tests/ui/guard_not_sync.rs");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn std_only_guards_stay_put() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/reentrant_guard_not_send.rs");
+    t.compile_fail("tests/ui/arc_guard_not_sync.rs");
+}
+
+#[cfg(not(feature = "send_guard"))]
+#[test]
+fn guard_is_not_send() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/guard_not_send.rs");
+}
@@ -0,0 +1,277 @@
+// Run with `cargo test --features loom --test loom --release`, debug builds make the exhaustive search painfully slow
+#![cfg(feature = "loom")]
+
+use loom::sync::atomic::{AtomicBool, Ordering};
+use loom::sync::Arc;
+use loom::thread;
+use my_mutex_learning::{
+    McsMutex, McsNode, ReentrantSpinMutex, SpinMutex, SpinMutexGuard, SpinRwLock,
+    SpinRwLockUpgradeableGuard, SpinRwLockWriteGuard, TicketMutex,
+};
+
+#[test]
+fn mutual_exclusion() {
+    loom::model(|| {
+        let m = Arc::new(SpinMutex::new(()));
+        let in_critical_section = Arc::new(AtomicBool::new(false));
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let m = m.clone();
+                let in_critical_section = in_critical_section.clone();
+                thread::spawn(move || {
+                    let _guard = m.lock();
+                    // Relaxed on purpose: the flag itself must not be what provides the exclusion
+                    assert!(!in_critical_section.swap(true, Ordering::Relaxed));
+                    in_critical_section.store(false, Ordering::Relaxed);
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    });
+}
+
+#[test]
+fn writes_are_visible_to_next_holder() {
+    loom::model(|| {
+        let m = Arc::new(SpinMutex::new((0, 0)));
+
+        let m2 = m.clone();
+        let writer = thread::spawn(move || {
+            let mut guard = m2.lock();
+            guard.0 = 1;
+            guard.1 = 2;
+        });
+
+        {
+            // Either we got in first and see nothing, or we see both writes, never half of them
+            let guard = m.lock();
+            assert!(*guard == (0, 0) || *guard == (1, 2));
+        }
+
+        writer.join().unwrap();
+        assert_eq!((1, 2), *m.lock());
+    });
+}
+
+#[test]
+fn increments_are_not_lost() {
+    loom::model(|| {
+        let m = Arc::new(SpinMutex::new(0));
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let m = m.clone();
+                thread::spawn(move || *m.lock() += 1)
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(2, *m.lock());
+    });
+}
+
+#[test]
+fn try_lock_never_overlaps_lock() {
+    loom::model(|| {
+        let m = Arc::new(SpinMutex::new(0));
+
+        let m2 = m.clone();
+        let locker = thread::spawn(move || *m2.lock() += 1);
+
+        if let Some(mut guard) = m.try_lock() {
+            *guard += 1;
+        }
+
+        locker.join().unwrap();
+        let total = *m.lock();
+        assert!(total == 1 || total == 2);
+    });
+}
+
+#[test]
+fn unlocked_and_mapped_guards_stay_exclusive() {
+    loom::model(|| {
+        let m = Arc::new(SpinMutex::new((0, 0)));
+
+        let m2 = m.clone();
+        let other = thread::spawn(move || m2.lock().0 += 1);
+
+        // Loom watches the data for as long as a guard can reach it, so this checks the hand-offs in between too
+        let mut guard = m.lock();
+        guard.1 += 1;
+        SpinMutexGuard::unlocked(&mut guard, || {});
+        let mut second = SpinMutexGuard::map(guard, |pair| &mut pair.1);
+        *second += 1;
+        drop(second);
+
+        other.join().unwrap();
+        assert_eq!((1, 2), *m.lock());
+    });
+}
+
+#[test]
+fn ticket_mutual_exclusion() {
+    loom::model(|| {
+        let m = Arc::new(TicketMutex::new(0));
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let m = m.clone();
+                thread::spawn(move || *m.lock() += 1)
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(2, *m.lock());
+    });
+}
+
+#[test]
+fn ticket_try_lock_never_overlaps_lock() {
+    loom::model(|| {
+        let m = Arc::new(TicketMutex::new(0));
+
+        let m2 = m.clone();
+        let locker = thread::spawn(move || *m2.lock() += 1);
+
+        if let Some(mut guard) = m.try_lock() {
+            *guard += 1;
+        }
+
+        locker.join().unwrap();
+        let total = *m.lock();
+        assert!(total == 1 || total == 2);
+    });
+}
+
+#[test]
+fn mcs_mutual_exclusion() {
+    loom::model(|| {
+        let m = Arc::new(McsMutex::new(0));
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let m = m.clone();
+                thread::spawn(move || *m.lock() += 1)
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(2, *m.lock());
+    });
+}
+
+#[test]
+fn mcs_stack_node_handoff() {
+    loom::model(|| {
+        let m = Arc::new(McsMutex::new((0, 0)));
+
+        let m2 = m.clone();
+        let writer = thread::spawn(move || {
+            let mut node = McsNode::new();
+            m2.lock_with(&mut node, |pair| *pair = (1, 2));
+        });
+
+        let mut node = McsNode::new();
+        let seen = m.lock_with(&mut node, |pair| *pair);
+        assert!(seen == (0, 0) || seen == (1, 2));
+
+        writer.join().unwrap();
+    });
+}
+
+#[test]
+fn rwlock_readers_see_whole_writes() {
+    loom::model(|| {
+        let l = Arc::new(SpinRwLock::new((0, 0)));
+
+        let l2 = l.clone();
+        let writer = thread::spawn(move || {
+            let mut guard = l2.write();
+            guard.0 = 1;
+            guard.1 = 2;
+        });
+
+        {
+            let guard = l.read();
+            assert!(*guard == (0, 0) || *guard == (1, 2));
+        }
+
+        writer.join().unwrap();
+    });
+}
+
+#[test]
+fn rwlock_upgrade_excludes_writer() {
+    loom::model(|| {
+        let l = Arc::new(SpinRwLock::new(0));
+
+        let l2 = l.clone();
+        let writer = thread::spawn(move || *l2.write() += 1);
+
+        {
+            let mut guard = SpinRwLockUpgradeableGuard::upgrade(l.upgradeable_read());
+            *guard += 1;
+        }
+
+        writer.join().unwrap();
+        assert_eq!(2, *l.read());
+    });
+}
+
+#[test]
+fn rwlock_downgrade_ends_the_write_first() {
+    loom::model(|| {
+        let l = Arc::new(SpinRwLock::new(0));
+
+        let l2 = l.clone();
+        let reader = thread::spawn(move || {
+            let seen = *l2.read();
+            assert!(seen == 0 || seen == 1);
+        });
+
+        let mut w = l.write();
+        *w = 1;
+        let r = SpinRwLockWriteGuard::downgrade(w);
+        assert_eq!(1, *r);
+        drop(r);
+
+        reader.join().unwrap();
+    });
+}
+
+#[test]
+fn reentrant_mutual_exclusion() {
+    loom::model(|| {
+        let m = Arc::new(ReentrantSpinMutex::new(()));
+        let in_critical_section = Arc::new(AtomicBool::new(false));
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let m = m.clone();
+                let in_critical_section = in_critical_section.clone();
+                thread::spawn(move || {
+                    let _outer = m.lock();
+                    assert!(!in_critical_section.swap(true, Ordering::Relaxed));
+                    // Re-entering mustn't let the other thread in once the inner guard goes away
+                    drop(m.lock());
+                    in_critical_section.store(false, Ordering::Relaxed);
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    });
+}
@@ -0,0 +1,13 @@
+use my_mutex_learning::SpinMutex;
+use std::sync::{Arc, Mutex};
+
+fn main() {
+    // A std `MutexGuard` is `Sync` but not `Send`, so the mutex around it isn't `Sync`. Sharing the guard would hand
+    // that mutex's `Arc` to another thread through `ArcSpinMutexGuard::mutex`.
+    static INNER: Mutex<()> = Mutex::new(());
+    let m = Arc::new(SpinMutex::new(INNER.lock().unwrap()));
+    let guard = m.lock_arc();
+    std::thread::scope(|s| {
+        s.spawn(|| drop(my_mutex_learning::ArcSpinMutexGuard::mutex(&guard).clone()));
+    });
+}
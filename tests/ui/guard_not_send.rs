@@ -0,0 +1,9 @@
+use my_mutex_learning::SpinMutex;
+
+fn main() {
+    let m = SpinMutex::new(0);
+    let guard = m.lock();
+    std::thread::scope(|s| {
+        s.spawn(move || drop(guard));
+    });
+}
@@ -0,0 +1,12 @@
+use my_mutex_learning::SpinMutex;
+use std::cell::Cell;
+
+fn main() {
+    // `SpinMutex<Cell<_>>` is fine to share, but a shared guard would let two threads call `Cell::set` together
+    let m = SpinMutex::new(Cell::new(0));
+    let guard = m.lock();
+    std::thread::scope(|s| {
+        s.spawn(|| guard.set(1));
+        guard.set(2);
+    });
+}
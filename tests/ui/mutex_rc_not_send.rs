@@ -0,0 +1,12 @@
+use my_mutex_learning::SpinMutex;
+use std::rc::Rc;
+
+fn main() {
+    // The other `Rc` stays behind on this thread, sharing a non-atomic refcount with the one we'd move
+    let rc = Rc::new(0);
+    let m = SpinMutex::new(rc.clone());
+    std::thread::spawn(move || {
+        let _ = m.lock();
+    });
+    drop(rc);
+}
@@ -0,0 +1,12 @@
+use my_mutex_learning::SpinMutex;
+use std::rc::Rc;
+use std::sync::Arc;
+
+fn main() {
+    // Cloning the `Rc` under the lock would let two threads bump its non-atomic refcount at once
+    let m = Arc::new(SpinMutex::new(Rc::new(0)));
+    let m2 = m.clone();
+    std::thread::spawn(move || {
+        let _ = m2.lock().clone();
+    });
+}
@@ -0,0 +1,10 @@
+use my_mutex_learning::ReentrantSpinMutex;
+
+fn main() {
+    // Not even with `send_guard`: the lock records which thread owns it, so it has to be unlocked there too
+    let m = ReentrantSpinMutex::new(0);
+    let guard = m.lock();
+    std::thread::scope(|s| {
+        s.spawn(move || drop(guard));
+    });
+}